@@ -0,0 +1,46 @@
+#version 330
+
+// Composites the person over the background. The mask is upsampled by the bilinear sampling of its
+// texture and feathered here, at the resolution of the drawn frame.
+
+in vec2 fragTexCoord;
+in vec4 fragColor;
+
+uniform sampler2D texture0;   // Person colors: camera frame or estimated foreground
+uniform sampler2D mask;       // Viewer mask, its alpha is the background coverage
+uniform sampler2D background; // Blurred frame or picture, for a texture background
+uniform vec2 maskTexel;       // Size of a mask texel in texture coordinates
+uniform float feather;        // Feather radius in mask texels, 0 to disable
+uniform int backgroundMode;   // 0 for backgroundColor, 1 for the background texture
+uniform vec4 backgroundColor;
+uniform vec4 backgroundRect;  // Offset and size of the shown part of the background texture
+
+out vec4 finalColor;
+
+float personAlpha(vec2 uv) {
+    return 1.0 - texture(mask, uv).a;
+}
+
+void main() {
+    float alpha = personAlpha(fragTexCoord);
+    if (feather > 0.0) {
+        // 5x5 tent filter spanning the feather radius
+        float sum = 0.0;
+        float weights = 0.0;
+        for (int y = -2; y <= 2; y++) {
+            for (int x = -2; x <= 2; x++) {
+                float weight = (3.0 - abs(float(x))) * (3.0 - abs(float(y)));
+                sum += weight * personAlpha(fragTexCoord + vec2(x, y) * 0.5 * feather * maskTexel);
+                weights += weight;
+            }
+        }
+        alpha = sum / weights;
+    }
+
+    vec3 person = texture(texture0, fragTexCoord).rgb;
+    vec3 back = backgroundColor.rgb;
+    if (backgroundMode == 1) {
+        back = texture(background, backgroundRect.xy + fragTexCoord * backgroundRect.zw).rgb;
+    }
+    finalColor = vec4(mix(back, person, alpha), 1.0) * fragColor;
+}
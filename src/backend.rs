@@ -0,0 +1,173 @@
+use anyhow::Result;
+use ndarray::Axis;
+use ort::inputs;
+use ort::session::Session;
+use ort::value::Tensor;
+
+use crate::SETUP;
+use crate::bgremoval;
+use crate::viewer::Frame;
+
+// Model input the backend expects
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputSpec {
+    pub width: u32,
+    pub height: u32,
+    pub batching: bool, // Several frames per run
+}
+
+// Runs person segmentation on model sized frames
+pub trait InferenceBackend: Send {
+    fn load(model: &str) -> Result<Self>
+    where
+        Self: Sized;
+
+    fn input_spec(&self) -> InputSpec;
+
+    // Person probability (0-1) of every frame, at the input size
+    fn run(&mut self, frames: &[&Frame]) -> Result<Vec<Vec<f32>>>;
+}
+
+// Loads `model` with the backend it names: "stub:ellipse" and "stub:brightness" need no model
+// file, anything else is an ONNX model path
+pub fn load(model: &str) -> Result<Box<dyn InferenceBackend>> {
+    Ok(match model.strip_prefix("stub:") {
+        Some(_) => Box::new(StubBackend::load(model)?),
+        None => Box::new(OrtBackend::load(model)?),
+    })
+}
+
+pub struct OrtBackend {
+    session: Session,
+    spec: InputSpec,
+}
+
+impl OrtBackend {
+    // Input size from the model's NCHW input, the low resolution size for dynamic dimensions
+    pub fn new(session: Session) -> Self {
+        let shape: Vec<i64> = session
+            .inputs
+            .first()
+            .and_then(|input| input.input_type.tensor_shape())
+            .map(|shape| shape.to_vec())
+            .unwrap_or_default();
+        let dim = |index: usize, default: u32| match shape.get(index) {
+            Some(&dim) if dim > 0 => dim as u32,
+            _ => default,
+        };
+
+        let spec = InputSpec {
+            width: dim(3, SETUP.small_dec_width),
+            height: dim(2, SETUP.small_dec_height),
+            batching: shape.first() == Some(&-1),
+        };
+        Self { session, spec }
+    }
+}
+
+impl InferenceBackend for OrtBackend {
+    // On the first working provider of `SETUP.session.providers`, validated and warmed up
+    fn load(model: &str) -> Result<Self> {
+        bgremoval::load_session(model)
+    }
+
+    fn input_spec(&self) -> InputSpec {
+        self.spec
+    }
+
+    fn run(&mut self, frames: &[&Frame]) -> Result<Vec<Vec<f32>>> {
+        let batch = frames.iter().map(|frame| frame.to_nchw_f32()).collect::<Vec<_>>();
+        let views = batch.iter().map(|input| input.view()).collect::<Vec<_>>();
+        let tensor = Tensor::from_array(ndarray::concatenate(Axis(0), &views)?)?;
+        let outputs = self.session.run(inputs![tensor])?;
+        let output = outputs["output"].try_extract_array::<f32>()?;
+
+        Ok(output.outer_iter().map(|mask| mask.iter().copied().collect()).collect())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StubMask {
+    Ellipse,    // Person sized ellipse in the lower middle of the frame, whatever the frame shows
+    Brightness, // Mean of the color channels, bright pixels are person
+}
+
+// Deterministic procedural masks, to work on the UI, compositing and outputs without a model
+pub struct StubBackend {
+    mask: StubMask,
+    width: u32,
+    height: u32,
+}
+
+impl StubBackend {
+    pub fn new(mask: StubMask) -> Self {
+        Self {
+            mask,
+            width: SETUP.small_dec_width,
+            height: SETUP.small_dec_height,
+        }
+    }
+
+    fn ellipse(&self) -> Vec<f32> {
+        let (width, height) = (self.width as f32, self.height as f32);
+        let (center_x, center_y) = (width * 0.5, height * 0.6);
+        let (radius_x, radius_y) = (width * 0.22, height * 0.42);
+        let edge = 2.0 / radius_x.min(radius_y); // Soft edge of about 2 pixels
+
+        (0..self.height)
+            .flat_map(|y| {
+                (0..self.width).map(move |x| {
+                    let dx = (x as f32 + 0.5 - center_x) / radius_x;
+                    let dy = (y as f32 + 0.5 - center_y) / radius_y;
+                    ((1.0 - (dx * dx + dy * dy).sqrt()) / edge + 0.5).clamp(0.0, 1.0)
+                })
+            })
+            .collect()
+    }
+}
+
+impl InferenceBackend for StubBackend {
+    fn load(model: &str) -> Result<Self> {
+        match model {
+            "stub:ellipse" => Ok(Self::new(StubMask::Ellipse)),
+            "stub:brightness" => Ok(Self::new(StubMask::Brightness)),
+            _ => Err(anyhow::anyhow!(
+                "Unknown stub model {}, expected stub:ellipse or stub:brightness",
+                model
+            )),
+        }
+    }
+
+    fn input_spec(&self) -> InputSpec {
+        InputSpec {
+            width: self.width,
+            height: self.height,
+            batching: true,
+        }
+    }
+
+    fn run(&mut self, frames: &[&Frame]) -> Result<Vec<Vec<f32>>> {
+        frames
+            .iter()
+            .map(|frame| {
+                if (frame.width as u32, frame.height as u32) != (self.width, self.height) {
+                    return Err(anyhow::anyhow!(
+                        "Frame is {}x{}, the stub expects {}x{}",
+                        frame.width,
+                        frame.height,
+                        self.width,
+                        self.height
+                    ));
+                }
+                Ok(match self.mask {
+                    StubMask::Ellipse => self.ellipse(),
+                    StubMask::Brightness => frame
+                        .as_rgb()
+                        .chunks_exact(3)
+                        .map(|px| (px[0] as f32 + px[1] as f32 + px[2] as f32) / (3.0 * 255.0))
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+}
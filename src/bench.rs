@@ -0,0 +1,259 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::SETUP;
+use crate::backend::InferenceBackend;
+use crate::bgremoval::{self, OpenedSession, Provider};
+use crate::imageio;
+use crate::roi::{self, Roi};
+use crate::viewer::Frame;
+use anyhow::Result;
+use fast_image_resize::PixelType;
+
+// Distinct synthetic frames cycled through when no recorded frames are given
+const SYNTHETIC_FRAMES: u32 = 8;
+
+struct BenchResult {
+    model: String,
+    provider: Provider,
+    batch: usize,
+    load: Duration,
+    warmup: Duration,
+    latencies: Vec<Duration>, // Sorted, one per model run
+    total: Duration,
+    peak_memory_kb: Option<u64>,
+}
+
+impl BenchResult {
+    fn mean(&self) -> Duration {
+        self.latencies.iter().sum::<Duration>() / self.latencies.len().max(1) as u32
+    }
+
+    fn percentile(&self, p: f32) -> Duration {
+        let index = ((self.latencies.len() as f32 * p).ceil() as usize).clamp(1, self.latencies.len()) - 1;
+        self.latencies[index]
+    }
+
+    fn throughput(&self) -> f32 {
+        (self.latencies.len() * self.batch) as f32 / self.total.as_secs_f32()
+    }
+
+    fn print(&self) {
+        println!("Model:       {}", self.model);
+        println!("Provider:    {:?}", self.provider);
+        println!("Batch:       {}", self.batch);
+        println!("Load:        {:.1} ms", ms(self.load));
+        println!("Warmup:      {:.1} ms", ms(self.warmup));
+        println!(
+            "Latency:     mean {:.2} ms, p50 {:.2} ms, p95 {:.2} ms, min {:.2} ms, max {:.2} ms",
+            ms(self.mean()),
+            ms(self.percentile(0.5)),
+            ms(self.percentile(0.95)),
+            ms(self.latencies[0]),
+            ms(self.latencies[self.latencies.len() - 1])
+        );
+        println!("Throughput:  {:.1} frames/s", self.throughput());
+        match self.peak_memory_kb {
+            Some(kb) => println!("Peak memory: {:.1} MiB (host)", kb as f32 / 1024.0),
+            None => println!("Peak memory: unavailable"),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            concat!(
+                "{{\n",
+                "  \"model\": {:?},\n",
+                "  \"provider\": {:?},\n",
+                "  \"batch\": {},\n",
+                "  \"runs\": {},\n",
+                "  \"load_ms\": {:.3},\n",
+                "  \"warmup_ms\": {:.3},\n",
+                "  \"mean_ms\": {:.3},\n",
+                "  \"p50_ms\": {:.3},\n",
+                "  \"p95_ms\": {:.3},\n",
+                "  \"min_ms\": {:.3},\n",
+                "  \"max_ms\": {:.3},\n",
+                "  \"throughput_fps\": {:.3},\n",
+                "  \"peak_memory_kb\": {}\n",
+                "}}\n"
+            ),
+            self.model,
+            format!("{:?}", self.provider),
+            self.batch,
+            self.latencies.len(),
+            ms(self.load),
+            ms(self.warmup),
+            ms(self.mean()),
+            ms(self.percentile(0.5)),
+            ms(self.percentile(0.95)),
+            ms(self.latencies[0]),
+            ms(self.latencies[self.latencies.len() - 1]),
+            self.throughput(),
+            self.peak_memory_kb.map_or("null".to_string(), |kb| kb.to_string()),
+        )
+    }
+}
+
+fn ms(duration: Duration) -> f32 {
+    duration.as_secs_f32() * 1000.0
+}
+
+// `bgremoval bench <model.onnx> [--provider tensorrt|cuda|cpu] [--runs N] [--warmup N] [--batch N]
+//  [--frames <dir>] [--json <output.json>]`
+pub fn bench_command(args: &[String]) -> Result<()> {
+    let [model, options @ ..] = args else {
+        return Err(anyhow::anyhow!(
+            "Usage: bgremoval bench <model.onnx> [--provider tensorrt|cuda|cpu] [--runs N] [--warmup N] [--batch N] [--frames <dir>] [--json <output.json>]"
+        ));
+    };
+
+    let mut providers = SETUP.session.providers.to_vec();
+    let mut runs = 100;
+    let mut warmup_runs = SETUP.session.warmup_runs;
+    let mut batch = 1;
+    let mut frames_dir = None;
+    let mut json = None;
+    for option in options.chunks(2) {
+        match option {
+            [name, value] if name == "--provider" => providers = vec![parse_provider(value)?],
+            [name, value] if name == "--runs" => runs = value.parse::<usize>()?.max(1),
+            [name, value] if name == "--warmup" => warmup_runs = value.parse()?,
+            [name, value] if name == "--batch" => batch = value.parse::<usize>()?.max(1),
+            [name, value] if name == "--frames" => frames_dir = Some(value),
+            [name, value] if name == "--json" => json = Some(value),
+            _ => return Err(anyhow::anyhow!("Unknown option: {:?}", option)),
+        }
+    }
+
+    let frames = match frames_dir {
+        Some(dir) => recorded_frames(Path::new(dir))?,
+        None => (0..SYNTHETIC_FRAMES).map(synthetic_frame).collect(),
+    };
+    println!("Benchmarking with {} frames", frames.len());
+
+    bgremoval::init_ort()?;
+    let OpenedSession {
+        mut backend,
+        provider,
+        load,
+        warmup,
+    } = bgremoval::open_first_provider(model, &providers, warmup_runs)?;
+    if batch > 1 && !backend.input_spec().batching {
+        return Err(anyhow::anyhow!("{} has a fixed batch size, use --batch 1", model));
+    }
+
+    let mut latencies = Vec::with_capacity(runs);
+    let mut inputs = frames.iter().cycle();
+    let start = Instant::now();
+    for _ in 0..runs {
+        let batch_frames: Vec<&Frame> = inputs.by_ref().take(batch).collect();
+        let run_start = Instant::now();
+        backend.run(&batch_frames)?;
+        latencies.push(run_start.elapsed());
+    }
+    let total = start.elapsed();
+    latencies.sort();
+
+    let result = BenchResult {
+        model: model.clone(),
+        provider,
+        batch,
+        load,
+        warmup,
+        latencies,
+        total,
+        peak_memory_kb: peak_memory_kb(),
+    };
+    result.print();
+    if let Some(json) = json {
+        std::fs::write(json, result.to_json())?;
+        println!("Results written to {}", json);
+    }
+    Ok(())
+}
+
+fn parse_provider(name: &str) -> Result<Provider> {
+    match name {
+        // TensorRT settings come from the configuration when it lists TensorRT
+        "tensorrt" | "trt" => Ok(SETUP
+            .session
+            .providers
+            .iter()
+            .copied()
+            .find(|provider| matches!(provider, Provider::TensorRt { .. }))
+            .unwrap_or(Provider::TensorRt {
+                engine_cache: None,
+                fp16: false,
+                int8: false,
+                workspace_size: 1 << 30,
+            })),
+        "cuda" => Ok(Provider::Cuda),
+        "cpu" => Ok(Provider::Cpu),
+        _ => Err(anyhow::anyhow!(
+            "Unknown provider {}, expected tensorrt, cuda or cpu",
+            name
+        )),
+    }
+}
+
+// Deterministic gradient and noise pattern at the model input size
+fn synthetic_frame(seed: u32) -> Frame {
+    let (width, height) = (SETUP.small_dec_width, SETUP.small_dec_height);
+    let mut state = seed.wrapping_mul(2654435761).wrapping_add(1);
+    let mut data = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let noise = (state >> 24) as u8 / 4;
+            data.extend_from_slice(&[
+                ((x * 255 / width) as u8).wrapping_add(noise),
+                ((y * 255 / height) as u8).wrapping_add(noise),
+                (seed * 32) as u8,
+                255,
+            ]);
+        }
+    }
+    Frame {
+        width: width as i32,
+        height: height as i32,
+        pixel_type: PixelType::U8x4,
+        data,
+    }
+}
+
+// JPEG and PNG frames of a directory, resized to the model input size
+fn recorded_frames(dir: &Path) -> Result<Vec<Frame>> {
+    let mut paths: Vec<_> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ["jpg", "jpeg", "png"].contains(&ext.to_ascii_lowercase().as_str()))
+        })
+        .collect();
+    paths.sort();
+    if paths.is_empty() {
+        return Err(anyhow::anyhow!("No JPEG or PNG frames in {}", dir.display()));
+    }
+
+    paths
+        .iter()
+        .map(|path| {
+            let frame = imageio::load_frame(path)?;
+            let full = Roi::full(frame.width as u32, frame.height as u32);
+            roi::crop_frame(&frame, full, SETUP.small_dec_width, SETUP.small_dec_height)
+        })
+        .collect()
+}
+
+// Peak resident set size of the process (Linux), host memory only
+fn peak_memory_kb() -> Option<u64> {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
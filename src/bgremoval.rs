@@ -1,25 +1,604 @@
 use crate::SETUP;
+use crate::backend::{self, InferenceBackend, OrtBackend};
+use crate::chroma::{self, ChromaKey, KeyColor};
+use crate::config::Config;
+use crate::foreground::{self, BlurFusion};
+use crate::hotswap::ModelSwap;
+use crate::matting;
+use crate::motion::{self, MotionField};
+use crate::plate::{self, PlateFusion, PlateSubtraction, Plates};
+use crate::postprocess::{self, MaskOp, TemporalSmoothing};
+use crate::refine::{self, MaskRefinement};
+use crate::roi::{self, Roi, RoiTracker, RoiTracking};
 use crate::viewer::{Frame, RaylibFrames};
 use anyhow::Result;
 use fast_image_resize::images::Image;
 use fast_image_resize::{FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer, SrcCropping};
-use std::ops::Mul;
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+use ort::execution_providers::*;
 use ort::session::Session;
-use ort::value::Tensor;
-use ort::{execution_providers::*, inputs};
+use ort::session::builder::GraphOptimizationLevel;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
+#[derive(Clone)]
 pub struct MlFrames {
+    pub source: usize, // Index of the camera the frames come from
     pub high_res_frame: Frame,
     pub low_res_frame: Frame,
     pub instant: Instant,
 }
 
-pub fn bgremoval(ml_rx: Receiver<MlFrames>, raylib_tx: Sender<RaylibFrames>) -> Result<()> {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InferenceCadence {
+    // Run the model on every frame
+    EveryFrame,
+    // Run the model on one frame out of N, reusing the mask in between
+    EveryNth(u32),
+    // Run the model at most this many times per second
+    TargetFps(f32),
+    // Run the model when the low resolution frame moved more than `motion_threshold`
+    // (mean absolute luma difference, 0-255) since the last inference, or after `max_skip` frames
+    Adaptive { motion_threshold: f32, max_skip: u32 },
+}
+
+// Saved form, e.g. `every_nth 3` or `adaptive 2 4`
+impl std::fmt::Display for InferenceCadence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InferenceCadence::EveryFrame => write!(f, "every_frame"),
+            InferenceCadence::EveryNth(n) => write!(f, "every_nth {}", n),
+            InferenceCadence::TargetFps(fps) => write!(f, "target_fps {}", fps),
+            InferenceCadence::Adaptive {
+                motion_threshold,
+                max_skip,
+            } => write!(f, "adaptive {} {}", motion_threshold, max_skip),
+        }
+    }
+}
+
+impl std::str::FromStr for InferenceCadence {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        Ok(match fields[..] {
+            ["every_frame"] => InferenceCadence::EveryFrame,
+            ["every_nth", n] => InferenceCadence::EveryNth(n.parse()?),
+            ["target_fps", fps] => InferenceCadence::TargetFps(fps.parse()?),
+            ["adaptive", motion_threshold, max_skip] => InferenceCadence::Adaptive {
+                motion_threshold: motion_threshold.parse()?,
+                max_skip: max_skip.parse()?,
+            },
+            _ => return Err(anyhow::anyhow!("Unknown inference cadence {}", text)),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Segmentation {
+    // Person segmentation with the model of `model_path`
+    Model,
+    // Keying against a green or blue screen, no model is loaded
+    ChromaKey(ChromaKey),
+    // Difference against a captured clean background plate, fused with the model mask unless
+    // `PlateFusion::PlateOnly`
+    PlateSubtraction(PlateSubtraction),
+}
+
+// Requests to the inference stage, from the viewer and the model file watcher
+#[derive(Clone, Debug)]
+pub enum Control {
+    LoadModel(PathBuf),  // Swap in the model at this path
+    CapturePlate(usize), // Capture the background plate of this source from its next frames
+    Settings(Settings),  // Replace the runtime settings
+}
+
+// Settings of the inference stage, starting from `SETUP`. The runtime settings can change while
+// running, the pipeline options are only read from `SETUP`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    pub mask_threshold: u8,         // Model person probability (0-255) above which a pixel is person
+    pub segmentation: Segmentation, // Parameters of the configured mode, the mode itself is fixed
+    pub feather: u32,               // Radius of the `MaskOp::Feather` steps of `mask_postprocess`, 0 drops them
+    pub temporal_smoothing: f32,    // Weight (0-1) of the previous mask in the shown mask, against flicker
+    pub inference_cadence: InferenceCadence,
+
+    // Pipeline options
+    pub mask_postprocess: &'static [MaskOp], // Its `MaskOp::Feather` steps are replaced by `feather`
+    pub mask_refinement: MaskRefinement,
+    pub foreground_estimation: Option<BlurFusion>,
+    pub roi_tracking: Option<RoiTracking>, // Fixed when the stage starts
+    pub motion_compensation: bool,
+    pub async_inference: bool, // Fixed when the stage starts
+    pub full_res_mask: bool,   // Build the full resolution mask even without a foreground to estimate
+}
+
+impl Settings {
+    pub fn from_setup() -> Self {
+        Self {
+            mask_threshold: SETUP.mask_threshold,
+            segmentation: SETUP.segmentation,
+            feather: SETUP
+                .mask_postprocess
+                .iter()
+                .filter_map(|op| match op {
+                    MaskOp::Feather(radius) => Some(*radius),
+                    _ => None,
+                })
+                .next_back()
+                .unwrap_or(0),
+            temporal_smoothing: SETUP.temporal_smoothing,
+            inference_cadence: SETUP.inference_cadence,
+            mask_postprocess: SETUP.mask_postprocess,
+            mask_refinement: SETUP.mask_refinement,
+            foreground_estimation: SETUP.foreground_estimation,
+            roi_tracking: SETUP.roi_tracking,
+            motion_compensation: SETUP.motion_compensation,
+            async_inference: SETUP.async_inference,
+            // The CPU blend and the shader with `full_res_mask` composite with it
+            full_res_mask: SETUP
+                .gpu_compositing
+                .is_none_or(|compositing| compositing.full_res_mask),
+        }
+    }
+
+    // `SETUP` with the settings saved to `SETUP.settings_path` on top
+    pub fn load() -> Self {
+        let mut settings = Self::from_setup();
+        match Config::load(Path::new(SETUP.settings_path)) {
+            Ok(config) => settings.read_config(&config),
+            Err(e) => eprintln!("Failed to read {}: {:?}", SETUP.settings_path, e),
+        }
+        settings
+    }
+
+    pub fn read_config(&mut self, config: &Config) {
+        config.read("mask_threshold", &mut self.mask_threshold);
+        config.read("feather", &mut self.feather);
+        config.read("temporal_smoothing", &mut self.temporal_smoothing);
+        config.read("inference_cadence", &mut self.inference_cadence);
+        match &mut self.segmentation {
+            Segmentation::Model => {}
+            Segmentation::ChromaKey(chroma_key) => {
+                if config.get("chroma_key.color") == Some("border") {
+                    chroma_key.key = KeyColor::Border;
+                } else if config.get("chroma_key.color").is_some() {
+                    let mut rgb = [0; 3];
+                    config.read_rgb("chroma_key.color", &mut rgb);
+                    chroma_key.key = KeyColor::Fixed(rgb);
+                }
+                config.read("chroma_key.tolerance", &mut chroma_key.tolerance);
+                config.read("chroma_key.softness", &mut chroma_key.softness);
+                config.read("chroma_key.spill", &mut chroma_key.spill);
+            }
+            Segmentation::PlateSubtraction(plate_subtraction) => {
+                config.read("plate.threshold", &mut plate_subtraction.threshold);
+                config.read("plate.softness", &mut plate_subtraction.softness);
+            }
+        }
+    }
+
+    pub fn write_config(&self, config: &mut Config) {
+        config.set("mask_threshold", self.mask_threshold);
+        config.set("feather", self.feather);
+        config.set("temporal_smoothing", self.temporal_smoothing);
+        config.set("inference_cadence", self.inference_cadence);
+        match self.segmentation {
+            Segmentation::Model => {}
+            Segmentation::ChromaKey(chroma_key) => {
+                match chroma_key.key {
+                    KeyColor::Fixed(rgb) => config.set_rgb("chroma_key.color", rgb),
+                    KeyColor::Border => config.set("chroma_key.color", "border"),
+                }
+                config.set("chroma_key.tolerance", chroma_key.tolerance);
+                config.set("chroma_key.softness", chroma_key.softness);
+                config.set("chroma_key.spill", chroma_key.spill);
+            }
+            Segmentation::PlateSubtraction(plate_subtraction) => {
+                config.set("plate.threshold", plate_subtraction.threshold);
+                config.set("plate.softness", plate_subtraction.softness);
+            }
+        }
+    }
+
+    // Person threshold of the segmentation mode: the model probability (0-255), the chroma key
+    // tolerance (0-1) or the plate difference threshold (0-255)
+    pub fn threshold(&self) -> f32 {
+        match self.segmentation {
+            Segmentation::Model => self.mask_threshold as f32,
+            Segmentation::ChromaKey(chroma_key) => chroma_key.tolerance,
+            Segmentation::PlateSubtraction(plate_subtraction) => plate_subtraction.threshold,
+        }
+    }
+
+    pub fn threshold_range(&self) -> (f32, f32) {
+        match self.segmentation {
+            Segmentation::Model => (0.0, 254.0),
+            Segmentation::ChromaKey(_) => (0.0, 1.0),
+            Segmentation::PlateSubtraction(_) => (0.0, 255.0),
+        }
+    }
+
+    pub fn set_threshold(&mut self, value: f32) {
+        let (min, max) = self.threshold_range();
+        let value = value.clamp(min, max);
+        match &mut self.segmentation {
+            Segmentation::Model => self.mask_threshold = value.round() as u8,
+            Segmentation::ChromaKey(chroma_key) => chroma_key.tolerance = value,
+            Segmentation::PlateSubtraction(plate_subtraction) => plate_subtraction.threshold = value,
+        }
+    }
+
+    // Raises (positive steps) or lowers the threshold by 1/50 of its range. Returns the new value.
+    pub fn adjust_threshold(&mut self, steps: i32) -> f32 {
+        let (min, max) = self.threshold_range();
+        self.set_threshold(self.threshold() + (max - min) / 50.0 * steps as f32);
+        self.threshold()
+    }
+
+    // `mask_postprocess` with a single feather step of these settings, last
+    pub fn mask_ops(&self) -> Vec<MaskOp> {
+        let mut ops: Vec<MaskOp> = self
+            .mask_postprocess
+            .iter()
+            .copied()
+            .filter(|op| !matches!(op, MaskOp::Feather(_)))
+            .collect();
+        if self.feather > 0 {
+            ops.push(MaskOp::Feather(self.feather));
+        }
+        ops
+    }
+}
+
+// Sampling step of the low resolution frame used for motion measurement and estimation
+const MOTION_SAMPLE_STEP: usize = 4;
+
+// Decides per frame whether to run inference or reuse the previous mask
+struct InferenceScheduler {
+    cadence: InferenceCadence,
+    frames_since_run: u32,
+    last_run: Option<Instant>,
+    last_luma: Vec<u8>,
+}
+
+impl InferenceScheduler {
+    fn new(cadence: InferenceCadence) -> Self {
+        Self {
+            cadence,
+            frames_since_run: 0,
+            last_run: None,
+            last_luma: Vec::new(),
+        }
+    }
+
+    fn should_run(&mut self, low_res_frame: &Frame) -> bool {
+        let luma = match self.cadence {
+            InferenceCadence::Adaptive { .. } => motion::sampled_luma(low_res_frame, MOTION_SAMPLE_STEP).0,
+            _ => Vec::new(),
+        };
+
+        let run = self.last_run.is_none()
+            || match self.cadence {
+                InferenceCadence::EveryFrame => true,
+                InferenceCadence::EveryNth(n) => self.frames_since_run + 1 >= n,
+                InferenceCadence::TargetFps(fps) => self
+                    .last_run
+                    .is_some_and(|last_run| last_run.elapsed() >= Duration::from_secs_f32(1.0 / fps.max(0.001))),
+                InferenceCadence::Adaptive {
+                    motion_threshold,
+                    max_skip,
+                } => {
+                    self.frames_since_run + 1 >= max_skip
+                        || motion::mean_abs_diff(&luma, &self.last_luma) > motion_threshold
+                }
+            };
+
+        if run {
+            self.frames_since_run = 0;
+            self.last_run = Some(Instant::now());
+            self.last_luma = luma;
+        } else {
+            self.frames_since_run += 1;
+        }
+        run
+    }
+}
+
+// Last inference result, with the sampled luma of the frame it was computed on
+#[derive(Clone)]
+struct InferredMask {
+    alpha: Vec<u8>,
+    instant: Instant,
+    luma: Vec<u8>,
+    inference_time: Duration,
+}
+
+impl InferredMask {
+    // The luma is only sampled with `motion_compensation`
+    fn new(
+        alpha: Vec<u8>,
+        low_res_frame: &Frame,
+        instant: Instant,
+        inference_time: Duration,
+        motion_compensation: bool,
+    ) -> Self {
+        let luma = if motion_compensation {
+            motion::sampled_luma(low_res_frame, MOTION_SAMPLE_STEP).0
+        } else {
+            Vec::new()
+        };
+        Self {
+            alpha,
+            instant,
+            luma,
+            inference_time,
+        }
+    }
+
+    // Alpha for another frame, warped to follow the motion since the inferred frame
+    fn alpha_for(&self, low_res_frame: &Frame, instant: Instant) -> Vec<u8> {
+        if self.luma.is_empty() || instant == self.instant {
+            return self.alpha.clone();
+        }
+        let (luma, width, height) = motion::sampled_luma(low_res_frame, MOTION_SAMPLE_STEP);
+        let field = MotionField::estimate(&self.luma, &luma, width, height, 8, 6);
+        motion::warp_mask(
+            &self.alpha,
+            low_res_frame.width as usize,
+            low_res_frame.height as usize,
+            &field,
+            1.0 / MOTION_SAMPLE_STEP as f32,
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum OptimizationLevel {
+    Disable,
+    Level1, // Basic graph rewrites, provider independent
+    Level2, // Extended fusions
+    Level3, // Layout optimizations
+}
+
+impl From<OptimizationLevel> for GraphOptimizationLevel {
+    fn from(level: OptimizationLevel) -> Self {
+        match level {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Level1 => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Level2 => GraphOptimizationLevel::Level2,
+            OptimizationLevel::Level3 => GraphOptimizationLevel::Level3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Provider {
+    TensorRt {
+        engine_cache: Option<&'static str>, // Directory for built engines and timing caches
+        fp16: bool,
+        int8: bool,            // Needs a calibration table, or a model with quantization nodes
+        workspace_size: usize, // Bytes TensorRT may use to build the engine
+    },
+    Cuda,
+    Cpu,
+}
+
+impl Provider {
+    pub fn name(self) -> &'static str {
+        match self {
+            Provider::TensorRt { .. } => "tensorrt",
+            Provider::Cuda => "cuda",
+            Provider::Cpu => "cpu",
+        }
+    }
+
+    fn dispatch(self) -> ExecutionProviderDispatch {
+        match self {
+            Provider::TensorRt {
+                engine_cache,
+                fp16,
+                int8,
+                workspace_size,
+            } => {
+                let mut ep = TensorRTExecutionProvider::default()
+                    .with_device_id(0)
+                    .with_fp16(fp16)
+                    .with_int8(int8)
+                    .with_max_workspace_size(workspace_size);
+                if let Some(engine_cache) = engine_cache {
+                    ep = ep
+                        .with_engine_cache(true)
+                        .with_engine_cache_path(engine_cache)
+                        .with_timing_cache(true)
+                        .with_timing_cache_path(engine_cache);
+                }
+                ep.build().error_on_failure()
+            }
+            Provider::Cuda => CUDAExecutionProvider::default()
+                .with_device_id(0)
+                .build()
+                .error_on_failure(),
+            Provider::Cpu => CPUExecutionProvider::default().build().error_on_failure(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SessionOptions {
+    pub providers: &'static [Provider], // In order of preference, the first that loads the model is used
+    pub intra_threads: usize,           // Threads used within an operator, 0 for the ONNX Runtime default
+    pub inter_threads: usize,           // Threads used across operators, 0 for the ONNX Runtime default
+    pub optimization_level: OptimizationLevel,
+    pub optimized_model_cache: Option<&'static str>, // Optimized model saved on first load and reused
+    pub profiling: Option<&'static str>,             // ONNX Runtime profile file prefix
+    pub warmup_runs: u32,                            // Blank runs validating the session, at least one
+}
+
+// Inference state of one source
+struct SourceInference {
+    scheduler: InferenceScheduler,
+    roi_tracker: Option<RoiTracker>,
+}
+
+impl SourceInference {
+    fn new(settings: &Settings) -> Self {
+        Self {
+            scheduler: InferenceScheduler::new(settings.inference_cadence),
+            roi_tracker: settings.roi_tracking.map(RoiTracker::new),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SourceStats {
+    frames: u32,
+    inferences: u32,
+    inference_time: Duration,
+    last_inferred: Option<Instant>,
+}
+
+// Per source frame and inference rates, printed every few seconds
+struct StatsReporter {
+    sources: Vec<SourceStats>,
+    since: Instant,
+}
+
+impl StatsReporter {
+    const INTERVAL: Duration = Duration::from_secs(5);
+
+    fn new(source_count: usize) -> Self {
+        Self {
+            sources: (0..source_count).map(|_| SourceStats::default()).collect(),
+            since: Instant::now(),
+        }
+    }
+
+    // Counts an output frame of `source` and the inference of its mask, once per mask
+    fn record(&mut self, source: usize, inferred: &InferredMask) {
+        let stats = &mut self.sources[source];
+        stats.frames += 1;
+        if stats.last_inferred != Some(inferred.instant) {
+            stats.last_inferred = Some(inferred.instant);
+            stats.inferences += 1;
+            stats.inference_time += inferred.inference_time;
+        }
+    }
+
+    fn report_if_due(&mut self) {
+        let elapsed = self.since.elapsed();
+        if elapsed < Self::INTERVAL {
+            return;
+        }
+        for (source, stats) in self.sources.iter_mut().enumerate() {
+            println!(
+                "[source {}] {:.1} fps, {:.1} inferences/s, mean inference {:.1} ms",
+                source,
+                stats.frames as f32 / elapsed.as_secs_f32(),
+                stats.inferences as f32 / elapsed.as_secs_f32(),
+                stats.inference_time.as_secs_f32() * 1000.0 / stats.inferences.max(1) as f32,
+            );
+            stats.frames = 0;
+            stats.inferences = 0;
+            stats.inference_time = Duration::ZERO;
+        }
+        self.since = Instant::now();
+    }
+}
+
+// `controls` receives model swaps and plate captures while running
+pub fn bgremoval(
+    ml_rx: Receiver<MlFrames>,
+    outputs: Vec<Sender<RaylibFrames>>,
+    controls: Receiver<Control>,
+) -> Result<()> {
+    let settings = Settings::load();
+    match settings.segmentation {
+        Segmentation::ChromaKey(_) => return chroma::run_chroma_key(settings, ml_rx, outputs, controls),
+        Segmentation::PlateSubtraction(PlateSubtraction {
+            fusion: PlateFusion::PlateOnly,
+            ..
+        }) => return plate::run_plate_subtraction(settings, ml_rx, outputs, controls),
+        _ => {}
+    }
+
+    init_ort()?;
+    let backend = backend::load(SETUP.model_path)?;
+    serve(backend, settings, ml_rx, outputs, controls)
+}
+
+// Runs the inference stage on an already loaded backend until the frame senders are gone
+pub fn serve(
+    backend: Box<dyn InferenceBackend>,
+    settings: Settings,
+    ml_rx: Receiver<MlFrames>,
+    outputs: Vec<Sender<RaylibFrames>>,
+    controls: Receiver<Control>,
+) -> Result<()> {
+    let (model_requests, model_rx) = std::sync::mpsc::channel();
+    let model_swap = ModelSwap::start(model_rx)?;
+    let stage_controls = StageControls {
+        controls,
+        model_requests,
+        settings: Arc::new(Mutex::new(settings)),
+        plates: Plates::new(outputs.len(), &settings),
+        smoothing: TemporalSmoothing::new(outputs.len()),
+    };
+
+    if settings.async_inference {
+        run_async(backend, model_swap, stage_controls, ml_rx, outputs)
+    } else {
+        run_sync(backend, model_swap, stage_controls, ml_rx, outputs)
+    }
+}
+
+// Control requests of the model inference loops and the state they act on
+struct StageControls {
+    controls: Receiver<Control>,
+    model_requests: Sender<PathBuf>, // To the model loader of `ModelSwap`
+    settings: Arc<Mutex<Settings>>,  // Shared with the inference thread
+    plates: Plates,
+    smoothing: TemporalSmoothing,
+}
+
+impl StageControls {
+    // Handles the pending requests, between two frames
+    fn apply(&mut self) {
+        for control in self.controls.try_iter() {
+            match control {
+                Control::LoadModel(path) => {
+                    let _ = self.model_requests.send(path);
+                }
+                Control::CapturePlate(source) => self.plates.capture(source),
+                Control::Settings(settings) => {
+                    self.plates.configure(&settings);
+                    *self.settings.lock().unwrap() = settings;
+                }
+            }
+        }
+    }
+
+    fn settings(&self) -> Settings {
+        *self.settings.lock().unwrap()
+    }
+
+    // Plate fusion and temporal smoothing of the alpha of a frame
+    fn finish_alpha(&mut self, source: usize, low_res_frame: &Frame, alpha: Vec<u8>) -> Vec<u8> {
+        let alpha = self.plates.apply(source, low_res_frame, alpha);
+        self.smoothing
+            .apply(source, alpha, self.settings.lock().unwrap().temporal_smoothing)
+    }
+}
+
+// Initializes tracing and the ONNX Runtime environment, once per process
+pub fn init_ort() -> Result<()> {
     // Initialize tracing to receive debug messages from `ort`
 
     tracing_subscriber::registry()
@@ -27,97 +606,627 @@ pub fn bgremoval(ml_rx: Receiver<MlFrames>, raylib_tx: Sender<RaylibFrames>) ->
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    // let ep = TensorRTExecutionProvider::default().with_device_id(0).build();
-    let ep = CUDAExecutionProvider::default().with_device_id(0).build();
+    // Execution providers are registered per session, see `load_session`
+    ort::init().with_name("BGRemoval").commit()?;
+    Ok(())
+}
+
+// Loads the model with the first of `SETUP.session.providers` that works, validated and warmed up
+// with blank runs so that a TensorRT engine that fails to build falls back to the next provider
+pub fn load_session(model_path: &str) -> Result<OrtBackend> {
+    let opened = open_first_provider(model_path, SETUP.session.providers, SETUP.session.warmup_runs.max(1))?;
+    println!("Running on {:?}", opened.provider);
+    Ok(opened.backend)
+}
+
+// Session of `open_first_provider`, with the provider it runs on and the time each step took
+pub struct OpenedSession {
+    pub backend: OrtBackend,
+    pub provider: Provider,
+    pub load: Duration,
+    pub warmup: Duration,
+}
+
+// Loads the model on the first of `providers` that loads and completes `warmup_runs` blank runs
+pub fn open_first_provider(model_path: &str, providers: &[Provider], warmup_runs: u32) -> Result<OpenedSession> {
+    let mut last_error = None;
+    for &provider in providers {
+        let start = Instant::now();
+        let opened = open_session(model_path, provider).and_then(|session| {
+            let load = start.elapsed();
+            let mut backend = OrtBackend::new(session);
+            let warmup_time = warmup(&mut backend, warmup_runs)?;
+            Ok(OpenedSession {
+                backend,
+                provider,
+                load,
+                warmup: warmup_time,
+            })
+        });
+        match opened {
+            Ok(opened) => return Ok(opened),
+            Err(e) => {
+                eprintln!("{:?} failed, trying the next provider: {:#}", provider, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No execution provider configured")))
+}
+
+// Builds the session on one execution provider with `SETUP.session`. With an optimized model cache
+// the graph optimizations run once and their result is saved; the cached model is loaded without
+// optimizing while it is newer than the model. Level 2/3 optimized models are specific to the
+// hardware and provider they were optimized on, so every provider has its own cache file.
+pub fn open_session(model_path: &str, provider: Provider) -> Result<Session> {
+    let options = SETUP.session;
+    let start = Instant::now();
+
+    let mut builder = Session::builder()?.with_execution_providers([provider.dispatch()])?;
+    if options.intra_threads > 0 {
+        builder = builder.with_intra_threads(options.intra_threads)?;
+    }
+    if options.inter_threads > 0 {
+        builder = builder.with_inter_threads(options.inter_threads)?;
+    }
+    if let Some(profiling) = options.profiling {
+        println!("ONNX Runtime profiling to {}_<timestamp>.json", profiling);
+        builder = builder.with_profiling(profiling)?;
+    }
+
+    let cache = options
+        .optimized_model_cache
+        .map(|cache| provider_cache(cache, provider));
+    let cached = cache.as_ref().filter(|cache| is_newer(cache, Path::new(model_path)));
+    let session = match (cached, &cache) {
+        (Some(cache), _) => {
+            println!("Loading optimized model from {}", cache.display());
+            builder
+                .with_optimization_level(GraphOptimizationLevel::Disable)?
+                .commit_from_file(cache)?
+        }
+        (None, Some(cache)) => {
+            println!("Optimizing {} into {}", model_path, cache.display());
+            builder
+                .with_optimization_level(options.optimization_level.into())?
+                .with_optimized_model_path(cache)?
+                .commit_from_file(model_path)?
+        }
+        (None, None) => builder
+            .with_optimization_level(options.optimization_level.into())?
+            .commit_from_file(model_path)?,
+    };
+
+    println!("Model loaded in {} ms", start.elapsed().as_millis());
+    Ok(session)
+}
+
+// Optimized model cache of one provider: "model.optimized.onnx" becomes "model.optimized.cuda.onnx"
+fn provider_cache(cache: &str, provider: Provider) -> PathBuf {
+    let path = Path::new(cache);
+    match path.extension() {
+        Some(extension) => path.with_extension(format!("{}.{}", provider.name(), extension.to_string_lossy())),
+        None => path.with_extension(provider.name()),
+    }
+}
 
-    ort::init()
-        .with_execution_providers([ep])
-        .with_name("BGRemoval")
-        .commit()?;
+fn is_newer(path: &Path, than: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    matches!((modified(path), modified(than)), (Some(a), Some(b)) if a >= b)
+}
 
-    let mut session = Session::builder()?
-        .with_optimization_level(ort::session::builder::GraphOptimizationLevel::Level3)?
-        .commit_from_file("models/model.onnx")?;
+// Runs the model on `runs` blank frames so lazy provider initialization and kernel selection
+// happen before the first camera frame, returns the time spent. Rejects backends whose input is
+// not the low resolution frame size.
+pub fn warmup(backend: &mut dyn InferenceBackend, runs: u32) -> Result<Duration> {
+    let spec = backend.input_spec();
+    if (spec.width, spec.height) != (SETUP.small_dec_width, SETUP.small_dec_height) {
+        return Err(anyhow::anyhow!(
+            "Model input is {}x{}, expected {}x{}",
+            spec.width,
+            spec.height,
+            SETUP.small_dec_width,
+            SETUP.small_dec_height
+        ));
+    }
+
+    let start = Instant::now();
+    let blank = Frame {
+        width: SETUP.small_dec_width as i32,
+        height: SETUP.small_dec_height as i32,
+        pixel_type: PixelType::U8x4,
+        data: vec![0; (SETUP.small_dec_width * SETUP.small_dec_height * 4) as usize],
+    };
+    let expected = (SETUP.small_dec_width * SETUP.small_dec_height) as usize;
+    for run in 0..runs {
+        let run_start = Instant::now();
+        let output = backend.run(&[&blank])?;
+        if output.first().map(Vec::len) != Some(expected) {
+            return Err(anyhow::anyhow!(
+                "Model output has {:?} values per frame, expected {}x{}",
+                output.first().map(Vec::len),
+                SETUP.small_dec_width,
+                SETUP.small_dec_height
+            ));
+        }
+        println!(
+            "Warmup run {}/{}: {} ms",
+            run + 1,
+            runs,
+            run_start.elapsed().as_millis()
+        );
+    }
+    Ok(start.elapsed())
+}
+
+// Inference and compositing in lockstep: one output frame per inference decision. Frames of
+// different sources arriving together are inferred in a single batch.
+fn run_sync(
+    mut backend: Box<dyn InferenceBackend>,
+    model_swap: ModelSwap,
+    mut stage_controls: StageControls,
+    ml_rx: Receiver<MlFrames>,
+    outputs: Vec<Sender<RaylibFrames>>,
+) -> Result<()> {
+    let settings = stage_controls.settings();
+    let mut sources: Vec<SourceInference> = outputs.iter().map(|_| SourceInference::new(&settings)).collect();
+    let mut inferred: Vec<Option<InferredMask>> = vec![None; outputs.len()];
+    let mut stats = StatsReporter::new(outputs.len());
+    let mut backlog: Vec<MlFrames> = Vec::new();
 
-    let mask_threshold = 235 as u8;
-    let mask_per_frame = 0; // use 0 to process every frame
-    let mut mask_per_frame_count = 0;
-    let mut mask = vec![];
     // Loop
+    loop {
+        if backlog.is_empty() {
+            let Ok(frames) = ml_rx.recv() else {
+                break;
+            };
+            backlog.push(frames);
+        }
+        backlog.extend(ml_rx.try_iter());
+        stage_controls.apply();
+        model_swap.apply(&mut backend);
+        for frames in &backlog {
+            check_source(frames.source, outputs.len())?;
+        }
+
+        // Oldest frame of every source, the others wait for the next round
+        let mut seen = vec![false; outputs.len()];
+        let (round, rest): (Vec<MlFrames>, Vec<MlFrames>) = backlog
+            .drain(..)
+            .partition(|frames| !std::mem::replace(&mut seen[frames.source], true));
+        backlog = rest;
+
+        let settings = stage_controls.settings();
+        let due: Vec<&MlFrames> = round
+            .iter()
+            .filter(|frames| {
+                let scheduler = &mut sources[frames.source].scheduler;
+                scheduler.cadence = settings.inference_cadence;
+                scheduler.should_run(&frames.low_res_frame)
+            })
+            .collect();
+        let masks = infer_batch(backend.as_mut(), &settings, &mut sources, &due)?;
+        for (frames, mask) in due.iter().zip(masks) {
+            inferred[frames.source] = Some(mask);
+        }
+
+        for MlFrames {
+            source,
+            high_res_frame,
+            low_res_frame,
+            instant,
+        } in round
+        {
+            // The scheduler always runs on the first frame of a source
+            let Some(inferred) = &inferred[source] else {
+                continue;
+            };
+            let alpha = inferred.alpha_for(&low_res_frame, instant);
+            let alpha = stage_controls.finish_alpha(source, &low_res_frame, alpha);
+            stats.record(source, inferred);
+
+            // Send all frames
+            outputs[source].send(compose(
+                high_res_frame,
+                low_res_frame,
+                &alpha,
+                inferred.instant,
+                instant,
+                &settings,
+            )?)?;
+        }
+        stats.report_if_due();
+    }
+    Ok(())
+}
+
+// Every camera frame is composited with the most recent mask of its source, while a separate
+// thread runs inference on whatever frames are newest when it becomes idle, batching sources
+fn run_async(
+    mut backend: Box<dyn InferenceBackend>,
+    model_swap: ModelSwap,
+    mut stage_controls: StageControls,
+    ml_rx: Receiver<MlFrames>,
+    outputs: Vec<Sender<RaylibFrames>>,
+) -> Result<()> {
+    let source_count = outputs.len();
+    let pending = Arc::new((
+        Mutex::new((0..source_count).map(|_| None).collect::<Vec<Option<MlFrames>>>()),
+        Condvar::new(),
+    ));
+    let latest_masks = Arc::new(Mutex::new(vec![None::<InferredMask>; source_count]));
+    let running = Arc::new(AtomicBool::new(true));
+
+    let worker = {
+        let pending = pending.clone();
+        let latest_masks = latest_masks.clone();
+        let running = running.clone();
+        let settings = stage_controls.settings.clone();
+        thread::Builder::new()
+            .name("inference".into())
+            .spawn(move || -> Result<()> {
+                let initial = *settings.lock().unwrap();
+                let mut sources: Vec<SourceInference> =
+                    (0..source_count).map(|_| SourceInference::new(&initial)).collect();
+                loop {
+                    let newest: Vec<MlFrames> = {
+                        let (slots, ready) = &*pending;
+                        let mut slots = slots.lock().unwrap();
+                        loop {
+                            let newest: Vec<MlFrames> = slots.iter_mut().filter_map(Option::take).collect();
+                            if !newest.is_empty() {
+                                break newest;
+                            }
+                            if !running.load(Ordering::Relaxed) {
+                                return Ok(());
+                            }
+                            slots = ready.wait(slots).unwrap();
+                        }
+                    };
+                    model_swap.apply(&mut backend);
+
+                    let settings = *settings.lock().unwrap();
+                    let due: Vec<&MlFrames> = newest
+                        .iter()
+                        .filter(|frames| {
+                            let scheduler = &mut sources[frames.source].scheduler;
+                            scheduler.cadence = settings.inference_cadence;
+                            scheduler.should_run(&frames.low_res_frame)
+                        })
+                        .collect();
+                    let masks = infer_batch(backend.as_mut(), &settings, &mut sources, &due)?;
+
+                    let mut latest_masks = latest_masks.lock().unwrap();
+                    for (frames, mask) in due.iter().zip(masks) {
+                        latest_masks[frames.source] = Some(mask);
+                    }
+                }
+            })?
+    };
+
+    let mut stats = StatsReporter::new(source_count);
     while let Ok(MlFrames {
+        source,
         high_res_frame,
         low_res_frame,
         instant,
     }) = ml_rx.recv()
     {
-        if mask_per_frame == 0 || mask_per_frame_count == 0 || mask_per_frame_count % mask_per_frame == 0 {
-            let tensor = Tensor::from_array(low_res_frame.to_nchw_f32())?;
-            let outputs = session.run(inputs![tensor])?;
-            let output = outputs["output"].try_extract_array::<f32>()?;
-            let output = output.mul(255.0).map(|x| *x as u8);
-            let output = output.into_raw_vec_and_offset();
-
-            mask = output
-                .0
-                .iter()
-                .flat_map(|&mask_val| {
-                    if mask_val > mask_threshold {
-                        vec![0, 0, 0, 0] // Transparent pixel (person)
-                    } else {
-                        vec![0, 255, 0, 255] // Green pixel, fully opaque (background)
-                    }
-                })
-                .collect::<Vec<u8>>()
+        if worker.is_finished() {
+            break;
         }
-        mask_per_frame_count += 1;
+        check_source(source, source_count)?;
+        stage_controls.apply();
 
-        let full_mask = resize_mask(
-            SETUP.small_dec_width,
-            SETUP.small_dec_height,
-            mask.clone().as_mut_slice(),
-            SETUP.full_dec_width,
-            SETUP.full_dec_height,
-        )?;
-
-        let ml_high_frame = Frame {
-            width: SETUP.full_dec_width as i32,
-            height: SETUP.full_dec_height as i32,
-            pixel_type: PixelType::U8x4,
-            data: full_mask,
+        // Replace any frame of this source the inference thread did not pick up yet
+        pending.0.lock().unwrap()[source] = Some(MlFrames {
+            source,
+            high_res_frame: high_res_frame.clone(),
+            low_res_frame: low_res_frame.clone(),
+            instant,
+        });
+        pending.1.notify_one();
+
+        let Some(inferred) = latest_masks.lock().unwrap()[source].clone() else {
+            continue; // No mask yet
         };
+        let alpha = inferred.alpha_for(&low_res_frame, instant);
+        let alpha = stage_controls.finish_alpha(source, &low_res_frame, alpha);
+        stats.record(source, &inferred);
 
-        //Send
+        if outputs[source]
+            .send(compose(
+                high_res_frame,
+                low_res_frame,
+                &alpha,
+                inferred.instant,
+                instant,
+                &stage_controls.settings(),
+            )?)
+            .is_err()
+        {
+            break;
+        }
+        stats.report_if_due();
+    }
 
-        let ml_low_frame = Frame {
-            width: low_res_frame.width,
-            height: low_res_frame.height,
-            pixel_type: PixelType::U8x4,
-            data: mask.clone(),
-        };
+    running.store(false, Ordering::Relaxed);
+    pending.1.notify_one();
+    worker
+        .join()
+        .map_err(|_| anyhow::anyhow!("Inference thread panicked"))?
+}
 
-        // Send all frames
-        raylib_tx.send(RaylibFrames {
-            high_res_frame,
-            low_res_frame,
-            ml_low_frame,
-            ml_high_frame,
-            instant,
-        })?;
+fn check_source(source: usize, source_count: usize) -> Result<()> {
+    if source >= source_count {
+        return Err(anyhow::anyhow!(
+            "Frame from source {} but only {} outputs",
+            source,
+            source_count
+        ));
     }
     Ok(())
 }
 
-fn resize_mask(
+// Runs inference on the frames of several sources, in a single model run when the model has a
+// dynamic batch dimension, and returns the post-processed person alpha of each at low resolution.
+// Each frame is fed at low resolution, or as the tracked region of its high resolution frame.
+fn infer_batch(
+    backend: &mut dyn InferenceBackend,
+    settings: &Settings,
+    sources: &mut [SourceInference],
+    frames: &[&MlFrames],
+) -> Result<Vec<InferredMask>> {
+    if frames.is_empty() {
+        return Ok(Vec::new());
+    }
+    let start = Instant::now();
+    let (width, height) = (SETUP.small_dec_width, SETUP.small_dec_height);
+
+    let crops = frames
+        .iter()
+        .map(|frames| {
+            let roi = sources[frames.source].roi_tracker.as_ref().and_then(RoiTracker::roi);
+            roi.map(|roi| Ok((roi, roi::crop_frame(&frames.high_res_frame, roi, width, height)?)))
+                .transpose()
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let inputs = frames
+        .iter()
+        .zip(crops.iter())
+        .map(|(frames, crop)| crop.as_ref().map_or(&frames.low_res_frame, |(_, crop)| crop))
+        .collect::<Vec<_>>();
+
+    let probabilities = if backend.input_spec().batching {
+        backend.run(&inputs)?
+    } else {
+        inputs
+            .iter()
+            .map(|&input| {
+                backend
+                    .run(&[input])?
+                    .pop()
+                    .ok_or_else(|| anyhow::anyhow!("Model returned an empty batch"))
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+    let inference_time = start.elapsed() / frames.len() as u32;
+
+    frames
+        .iter()
+        .zip(crops)
+        .zip(probabilities)
+        .map(|((frames, crop), probability)| {
+            let (frame_width, frame_height) = (frames.high_res_frame.width as u32, frames.high_res_frame.height as u32);
+            let alpha = threshold_alpha(&probability, settings.mask_threshold);
+            let alpha = match crop {
+                Some((roi, _)) => {
+                    let region = roi.scaled(frame_width, frame_height, width, height);
+                    roi::paste_alpha(&alpha, width, height, region, width, height)?
+                }
+                None => alpha,
+            };
+
+            let alpha = postprocess::apply(&alpha, width as usize, height as usize, &settings.mask_ops());
+            if let Some(roi_tracker) = sources[frames.source].roi_tracker.as_mut() {
+                roi_tracker.update(&alpha, width, height, frame_width, frame_height);
+            }
+            Ok(InferredMask::new(
+                alpha,
+                &frames.low_res_frame,
+                frames.instant,
+                inference_time,
+                settings.motion_compensation,
+            ))
+        })
+        .collect()
+}
+
+// Thresholds the model person probability (0-1) into an alpha (255 = person)
+fn threshold_alpha(probability: &[f32], mask_threshold: u8) -> Vec<u8> {
+    probability
+        .iter()
+        .map(|&mask_val| {
+            if (mask_val * 255.0) as u8 > mask_threshold {
+                255
+            } else {
+                0
+            }
+        })
+        .collect::<Vec<u8>>()
+}
+
+// Builds the viewer frames from the camera frames and the low resolution person alpha. The full
+// resolution mask is only built when the viewer composites with it or a foreground is estimated
+// from it, otherwise the viewer upsamples the low resolution mask.
+pub fn compose(
+    high_res_frame: Frame,
+    low_res_frame: Frame,
+    alpha: &[u8],
+    mask_instant: Instant,
+    instant: Instant,
+    settings: &Settings,
+) -> Result<RaylibFrames> {
+    let needs_full_mask = settings.full_res_mask
+        || settings.foreground_estimation.is_some()
+        || matches!(settings.mask_refinement, MaskRefinement::Matting { .. });
+    let (full_mask, foreground) = if needs_full_mask {
+        let (full_mask, foreground) = refine_mask(&high_res_frame, alpha, settings.mask_refinement)?;
+        let foreground = decontaminate(&high_res_frame, &full_mask, foreground, settings.foreground_estimation)?;
+        (Some(full_mask), foreground)
+    } else {
+        (None, None)
+    };
+    Ok(viewer_frames(
+        high_res_frame,
+        low_res_frame,
+        alpha_to_mask(alpha),
+        full_mask,
+        foreground,
+        mask_instant,
+        instant,
+    ))
+}
+
+// Estimates the person colors on the mask edges with `foreground_estimation`, starting from the
+// foreground colors of the refinement when it has them
+pub fn decontaminate(
+    high_res_frame: &Frame,
+    full_mask: &[u8],
+    foreground: Option<Frame>,
+    foreground_estimation: Option<BlurFusion>,
+) -> Result<Option<Frame>> {
+    let Some(blur_fusion) = foreground_estimation else {
+        return Ok(foreground);
+    };
+    let alpha: Vec<u8> = full_mask.chunks_exact(4).map(|m| 255 - m[3]).collect();
+    let frame = foreground.as_ref().unwrap_or(high_res_frame);
+    Ok(Some(foreground::estimate_foreground(frame, &alpha, blur_fusion)?))
+}
+
+// Wraps the camera frames and their RGBA viewer masks at both resolutions into the viewer frames
+pub fn viewer_frames(
+    high_res_frame: Frame,
+    low_res_frame: Frame,
+    mask: Vec<u8>,
+    full_mask: Option<Vec<u8>>,
+    foreground: Option<Frame>,
+    mask_instant: Instant,
+    instant: Instant,
+) -> RaylibFrames {
+    let ml_high_frame = full_mask.map(|data| Frame {
+        width: high_res_frame.width,
+        height: high_res_frame.height,
+        pixel_type: PixelType::U8x4,
+        data,
+    });
+
+    let ml_low_frame = Frame {
+        width: low_res_frame.width,
+        height: low_res_frame.height,
+        pixel_type: PixelType::U8x4,
+        data: mask,
+    };
+
+    RaylibFrames {
+        high_res_frame,
+        low_res_frame,
+        ml_low_frame,
+        ml_high_frame,
+        foreground,
+        instant,
+        mask_age: instant.saturating_duration_since(mask_instant),
+    }
+}
+
+// Brings the low resolution person alpha to the resolution of the frame, as an RGBA viewer mask,
+// with the estimated foreground colors when the refinement produces them
+fn refine_mask(high_res_frame: &Frame, alpha: &[u8], refinement: MaskRefinement) -> Result<(Vec<u8>, Option<Frame>)> {
+    match refinement {
+        MaskRefinement::Upsample => Ok((
+            resize_mask(
+                SETUP.small_dec_width,
+                SETUP.small_dec_height,
+                alpha_to_mask(alpha).as_mut_slice(),
+                high_res_frame.width as u32,
+                high_res_frame.height as u32,
+            )?,
+            None,
+        )),
+        MaskRefinement::GuidedFilter { radius, eps, subsample } => Ok((
+            alpha_to_mask(&refine::guided_upsample(
+                high_res_frame,
+                alpha,
+                SETUP.small_dec_width,
+                SETUP.small_dec_height,
+                radius,
+                eps,
+                subsample,
+            )?),
+            None,
+        )),
+        MaskRefinement::Matting { band, chroma_key } => {
+            let matte = matting::matte(
+                high_res_frame,
+                alpha,
+                SETUP.small_dec_width,
+                SETUP.small_dec_height,
+                band,
+                chroma_key,
+            );
+            Ok((alpha_to_mask(&matte.alpha), Some(matte.foreground)))
+        }
+    }
+}
+
+// Runs a still image through the pipeline stages (model input resize, inference, post-processing,
+// refinement) and returns the person alpha (255 = person) at the image resolution
+pub fn predict_alpha(
+    backend: &mut dyn InferenceBackend,
+    frame: &Frame,
+    postprocess_ops: &[MaskOp],
+    refinement: MaskRefinement,
+) -> Result<Vec<u8>> {
+    let (width, height) = (SETUP.small_dec_width, SETUP.small_dec_height);
+    let low_res_frame = roi::crop_frame(frame, Roi::full(frame.width as u32, frame.height as u32), width, height)?;
+    let probability = backend
+        .run(&[&low_res_frame])?
+        .pop()
+        .ok_or_else(|| anyhow::anyhow!("Model returned an empty batch"))?;
+    let alpha = postprocess::apply(
+        &threshold_alpha(&probability, SETUP.mask_threshold),
+        width as usize,
+        height as usize,
+        postprocess_ops,
+    );
+
+    let (mask, _) = refine_mask(frame, &alpha, refinement)?;
+    Ok(mask.chunks_exact(4).map(|m| 255 - m[3]).collect())
+}
+
+// Person alpha (255 = person) to the RGBA mask used by the viewer:
+// transparent on the person, opaque green on the background.
+pub fn alpha_to_mask(alpha: &[u8]) -> Vec<u8> {
+    alpha
+        .iter()
+        .flat_map(|&a| {
+            let background = 255 - a;
+            [0, background, 0, background]
+        })
+        .collect()
+}
+
+pub fn resize_mask(
     src_width: u32,
     src_height: u32,
-    mut src_data: &mut [u8],
+    src_data: &mut [u8],
     dst_width: u32,
     dst_height: u32,
 ) -> Result<Vec<u8>, anyhow::Error> {
     let mut resizer = Resizer::new();
 
     // Create source image
-    let src_img = Image::from_slice_u8(src_width, src_height, &mut src_data, PixelType::U8x4)?;
+    let src_img = Image::from_slice_u8(src_width, src_height, src_data, PixelType::U8x4)?;
 
     // Create destination image
     let mut dst_img = Image::new(dst_width, dst_height, PixelType::U8x4);
@@ -133,7 +1242,11 @@ fn resize_mask(
     Ok(dst_img.into_vec())
 }
 
-pub fn run_inference(ml_rx: Receiver<MlFrames>, raylib_tx: Sender<RaylibFrames>) -> Result<()> {
-    bgremoval(ml_rx, raylib_tx)?;
+pub fn run_inference(
+    ml_rx: Receiver<MlFrames>,
+    outputs: Vec<Sender<RaylibFrames>>,
+    controls: Receiver<Control>,
+) -> Result<()> {
+    bgremoval(ml_rx, outputs, controls)?;
     Ok(())
 }
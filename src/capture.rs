@@ -1,7 +1,10 @@
 use anyhow::Result;
 use std::{sync::mpsc::Sender, time::Instant};
 
-use v4l::{io::traits::CaptureStream, prelude::MmapStream};
+use v4l::control::{self, Control};
+use v4l::{Device, io::traits::CaptureStream, prelude::MmapStream};
+
+use crate::config::Config;
 
 #[inline(always)]
 pub fn capture(tx: Sender<Vec<u8>>, mut stream: MmapStream) -> Result<()> {
@@ -42,3 +45,112 @@ pub fn capture(tx: Sender<Vec<u8>>, mut stream: MmapStream) -> Result<()> {
     println!("Capture stopped after {:?}", start.elapsed());
     Ok(())
 }
+
+// Integer or boolean V4L2 control of a camera, such as exposure, gain or white balance temperature
+pub struct CameraControl {
+    pub id: u32,
+    pub name: String,
+    pub typ: control::Type, // Integer or Boolean, booleans hold 0 or 1
+    pub minimum: i64,
+    pub maximum: i64,
+    pub default: i64,
+    pub value: i64,
+}
+
+// Adjustable controls of one camera for the viewer settings panel. The device is opened a second
+// time next to the capture stream, which V4L2 allows for controls.
+pub struct CameraControls {
+    device: Option<Device>,
+    pub controls: Vec<CameraControl>,
+}
+
+impl CameraControls {
+    // No controls when the device cannot be opened or queried
+    pub fn open(index: usize) -> Self {
+        let device = match Device::new(index) {
+            Ok(device) => device,
+            Err(e) => {
+                eprintln!("Camera {} controls unavailable: {:?}", index, e);
+                return Self {
+                    device: None,
+                    controls: Vec::new(),
+                };
+            }
+        };
+        let controls = device
+            .query_controls()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|description| {
+                matches!(description.typ, control::Type::Integer | control::Type::Boolean)
+                    && !description
+                        .flags
+                        .intersects(control::Flags::DISABLED | control::Flags::READ_ONLY | control::Flags::INACTIVE)
+            })
+            .filter_map(|description| {
+                let value = match device.control(description.id).ok()?.value {
+                    control::Value::Integer(value) => value,
+                    control::Value::Boolean(value) => value as i64,
+                    _ => return None,
+                };
+                Some(CameraControl {
+                    id: description.id,
+                    name: description.name,
+                    typ: description.typ,
+                    minimum: description.minimum,
+                    maximum: description.maximum,
+                    default: description.default,
+                    value,
+                })
+            })
+            .collect();
+        Self {
+            device: Some(device),
+            controls,
+        }
+    }
+
+    // Applies the values saved for the camera of `source`
+    pub fn read_config(&mut self, config: &Config, source: usize) {
+        for i in 0..self.controls.len() {
+            let control = &self.controls[i];
+            let mut value = control.value;
+            config.read(&format!("camera.{}.{}", source, control.id), &mut value);
+            if value != control.value {
+                self.set(i, value);
+            }
+        }
+    }
+
+    pub fn write_config(&self, config: &mut Config, source: usize) {
+        for control in &self.controls {
+            config.set(&format!("camera.{}.{}", source, control.id), control.value);
+        }
+    }
+
+    // Back to the driver defaults
+    pub fn reset(&mut self) {
+        for i in 0..self.controls.len() {
+            self.set(i, self.controls[i].default);
+        }
+    }
+
+    // Sets control `i` (index in `controls`), keeping the previous value if the camera refuses it
+    pub fn set(&mut self, i: usize, value: i64) {
+        let (Some(device), Some(control)) = (&self.device, self.controls.get_mut(i)) else {
+            return;
+        };
+        let value = value.clamp(control.minimum, control.maximum);
+        let control_value = match control.typ {
+            control::Type::Boolean => control::Value::Boolean(value != 0),
+            _ => control::Value::Integer(value),
+        };
+        match device.set_control(Control {
+            id: control.id,
+            value: control_value,
+        }) {
+            Ok(()) => control.value = value,
+            Err(e) => eprintln!("Failed to set {} to {}: {:?}", control.name, value, e),
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use std::sync::mpsc::{Receiver, Sender};
+
+use anyhow::Result;
+use fast_image_resize::Resizer;
+
+use crate::bgremoval::{self, Control, MlFrames, Segmentation, Settings};
+use crate::postprocess::{self, MaskOp, TemporalSmoothing};
+use crate::refine::resize_plane;
+use crate::viewer::{Frame, RaylibFrames};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeySpace {
+    // Hue distance, with saturation and value weighted lower so shadows on the backdrop still key
+    Hsv,
+    // Distance in the CbCr chroma plane, ignoring luma entirely
+    YCbCr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyColor {
+    // Backdrop color in RGB
+    Fixed([u8; 3]),
+    // Median color of the frame border on the first frame of each camera, for a backdrop that
+    // fills the edges of the picture
+    Border,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChromaKey {
+    pub key: KeyColor,
+    pub space: KeySpace,
+    pub tolerance: f32, // Color distance (0-1) below which a pixel is backdrop
+    pub softness: f32,  // Distance range above the tolerance over which alpha ramps up to person
+    pub spill: f32,     // Strength (0-1) of the key color removal on the person, 0 to disable
+}
+
+// Person alpha (255 = person) of a frame keyed against `key`
+pub fn key_alpha(frame: &Frame, key: [u8; 3], chroma_key: &ChromaKey) -> Vec<u8> {
+    let key_color = to_space(key, chroma_key.space);
+    frame
+        .data
+        .chunks_exact(frame.pixel_type.size())
+        .map(|px| keyed_alpha([px[0], px[1], px[2]], key_color, chroma_key))
+        .collect()
+}
+
+// Person alpha (255 = person) of a single pixel keyed against `key`
+pub fn pixel_alpha(rgb: [u8; 3], key: [u8; 3], chroma_key: &ChromaKey) -> u8 {
+    keyed_alpha(rgb, to_space(key, chroma_key.space), chroma_key)
+}
+
+fn keyed_alpha(rgb: [u8; 3], key_color: [f32; 3], chroma_key: &ChromaKey) -> u8 {
+    let distance = color_distance(to_space(rgb, chroma_key.space), key_color, chroma_key.space);
+    let alpha = if chroma_key.softness > 0.0 {
+        ((distance - chroma_key.tolerance) / chroma_key.softness).clamp(0.0, 1.0)
+    } else if distance > chroma_key.tolerance {
+        1.0
+    } else {
+        0.0
+    };
+    (alpha * 255.0).round() as u8
+}
+
+// Pulls the channel the key color is strongest in down towards the other two channels, removing
+// the backdrop color reflected onto the person
+pub fn suppress_spill(frame: &mut Frame, key: [u8; 3], amount: f32) {
+    for px in frame.data.chunks_exact_mut(frame.pixel_type.size()) {
+        suppress_pixel_spill(px, key, amount);
+    }
+}
+
+// Spill suppression of the RGB(A) pixel `px`, see `suppress_spill`
+pub fn suppress_pixel_spill(px: &mut [u8], key: [u8; 3], amount: f32) {
+    let dominant = (0..3).max_by_key(|&c| key[c]).unwrap_or(1);
+    let limit = px[(dominant + 1) % 3].max(px[(dominant + 2) % 3]);
+    let spill = px[dominant].saturating_sub(limit);
+    px[dominant] -= (spill as f32 * amount.clamp(0.0, 1.0)).round() as u8;
+}
+
+// Mean color of the square of `radius` pixels around (x, y), clamped to the frame
+pub fn pick_key_color(frame: &Frame, x: u32, y: u32, radius: u32) -> [u8; 3] {
+    let (width, height) = (frame.width as u32, frame.height as u32);
+    let channels = frame.pixel_type.size();
+    let (mut sum, mut count) = ([0u64; 3], 0u64);
+    for py in y.saturating_sub(radius)..(y + radius + 1).min(height) {
+        for px in x.saturating_sub(radius)..(x + radius + 1).min(width) {
+            let i = (py * width + px) as usize * channels;
+            for (sum, &value) in sum.iter_mut().zip(&frame.data[i..i + 3]) {
+                *sum += value as u64;
+            }
+            count += 1;
+        }
+    }
+    sum.map(|s| (s / count.max(1)) as u8)
+}
+
+// Per channel median of the outermost rows and columns of the frame
+pub fn border_key_color(frame: &Frame) -> [u8; 3] {
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let channels = frame.pixel_type.size();
+    let border = (0..height).flat_map(|y| {
+        let columns: Vec<usize> = if y == 0 || y + 1 == height {
+            (0..width).collect()
+        } else {
+            vec![0, width - 1]
+        };
+        columns.into_iter().map(move |x| (y * width + x) * channels)
+    });
+
+    let mut samples: [Vec<u8>; 3] = Default::default();
+    for i in border {
+        for (channel, &value) in samples.iter_mut().zip(&frame.data[i..i + 3]) {
+            channel.push(value);
+        }
+    }
+    samples.map(|mut channel| {
+        channel.sort_unstable();
+        channel.get(channel.len() / 2).copied().unwrap_or(0)
+    })
+}
+
+// RGB to HSV (hue in turns) or YCbCr (BT.601 full range), all components 0-1
+fn to_space(rgb: [u8; 3], space: KeySpace) -> [f32; 3] {
+    let [r, g, b] = rgb.map(|c| c as f32 / 255.0);
+    match space {
+        KeySpace::Hsv => {
+            let max = r.max(g).max(b);
+            let chroma = max - r.min(g).min(b);
+            let hue = if chroma == 0.0 {
+                0.0
+            } else if max == r {
+                ((g - b) / chroma).rem_euclid(6.0)
+            } else if max == g {
+                (b - r) / chroma + 2.0
+            } else {
+                (r - g) / chroma + 4.0
+            };
+            let saturation = if max > 0.0 { chroma / max } else { 0.0 };
+            [hue / 6.0, saturation, max]
+        }
+        KeySpace::YCbCr => [
+            0.299 * r + 0.587 * g + 0.114 * b,
+            0.5 - 0.168736 * r - 0.331264 * g + 0.5 * b,
+            0.5 + 0.5 * r - 0.418688 * g - 0.081312 * b,
+        ],
+    }
+}
+
+// Distance between two colors of `space`, 0 for the same color and about 1 for unrelated colors
+fn color_distance(color: [f32; 3], key: [f32; 3], space: KeySpace) -> f32 {
+    match space {
+        KeySpace::Hsv => {
+            let hue = (color[0] - key[0]).abs();
+            let hue = hue.min(1.0 - hue) * 2.0;
+            let saturation = color[1] - key[1];
+            let value = color[2] - key[2];
+            (hue * hue + 0.25 * saturation * saturation + 0.0625 * value * value).sqrt()
+        }
+        KeySpace::YCbCr => {
+            let (cb, cr) = (color[1] - key[1], color[2] - key[2]);
+            ((cb * cb + cr * cr).sqrt() * 2.0).min(1.0)
+        }
+    }
+}
+
+// Keys every frame against its camera's backdrop color instead of running a model, producing the
+// same viewer masks. Both resolutions are keyed directly, so there is no refinement step. The mask
+// post-processing runs on the low resolution alpha, as on a model mask, and the full resolution
+// alpha follows it; feathering and temporal smoothing apply to both.
+pub fn run_chroma_key(
+    mut settings: Settings,
+    ml_rx: Receiver<MlFrames>,
+    outputs: Vec<Sender<RaylibFrames>>,
+    controls: Receiver<Control>,
+) -> Result<()> {
+    let Segmentation::ChromaKey(mut chroma_key) = settings.segmentation else {
+        return Err(anyhow::anyhow!("Chroma key stage started without chroma key settings"));
+    };
+    let mut mask_ops = shape_ops(&settings);
+    let mut resizer = Resizer::new();
+    let mut keys: Vec<Option<[u8; 3]>> = vec![None; outputs.len()];
+    let mut low_smoothing = TemporalSmoothing::new(outputs.len());
+    let mut high_smoothing = TemporalSmoothing::new(outputs.len());
+    println!("Chroma key mode: {:?}", chroma_key);
+
+    while let Ok(MlFrames {
+        source,
+        mut high_res_frame,
+        low_res_frame,
+        instant,
+    }) = ml_rx.recv()
+    {
+        for control in controls.try_iter() {
+            match control {
+                Control::Settings(new_settings) => {
+                    if let Segmentation::ChromaKey(new_chroma_key) = new_settings.segmentation {
+                        chroma_key = new_chroma_key;
+                    }
+                    settings = new_settings;
+                    mask_ops = shape_ops(&settings);
+                }
+                control => eprintln!("Chroma key mode, {:?} ignored", control),
+            }
+        }
+        let Some(output) = outputs.get(source) else {
+            return Err(anyhow::anyhow!(
+                "Frame from source {} but only {} outputs",
+                source,
+                outputs.len()
+            ));
+        };
+
+        let key = match chroma_key.key {
+            KeyColor::Fixed(rgb) => rgb,
+            KeyColor::Border => *keys[source].get_or_insert_with(|| {
+                let rgb = border_key_color(&low_res_frame);
+                println!("Source {} key color {:?}", source, rgb);
+                rgb
+            }),
+        };
+
+        let (low_width, low_height) = (low_res_frame.width as usize, low_res_frame.height as usize);
+        let (high_width, high_height) = (high_res_frame.width as usize, high_res_frame.height as usize);
+        let shape = postprocess::apply(
+            &key_alpha(&low_res_frame, key, &chroma_key),
+            low_width,
+            low_height,
+            &mask_ops,
+        );
+        let high_alpha = follow_shape(
+            &key_alpha(&high_res_frame, key, &chroma_key),
+            &high_res_frame,
+            &shape,
+            &low_res_frame,
+            &mut resizer,
+        )?;
+
+        // Same feather in frame terms at both resolutions
+        let high_feather = settings.feather * high_width as u32 / low_width.max(1) as u32;
+        let low_alpha = postprocess::feather(&shape, low_width, low_height, settings.feather);
+        let low_alpha = low_smoothing.apply(source, low_alpha, settings.temporal_smoothing);
+        let high_alpha = postprocess::feather(&high_alpha, high_width, high_height, high_feather);
+        let high_alpha = high_smoothing.apply(source, high_alpha, settings.temporal_smoothing);
+        if chroma_key.spill > 0.0 {
+            suppress_spill(&mut high_res_frame, key, chroma_key.spill);
+        }
+
+        let full_mask = bgremoval::alpha_to_mask(&high_alpha);
+        let foreground = bgremoval::decontaminate(&high_res_frame, &full_mask, None, settings.foreground_estimation)?;
+        let frames = bgremoval::viewer_frames(
+            high_res_frame,
+            low_res_frame,
+            bgremoval::alpha_to_mask(&low_alpha),
+            Some(full_mask),
+            foreground,
+            instant,
+            instant,
+        );
+        if output.send(frames).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// `mask_postprocess` steps that change the shape of the mask, without the feathering
+fn shape_ops(settings: &Settings) -> Vec<MaskOp> {
+    settings
+        .mask_ops()
+        .into_iter()
+        .filter(|op| !matches!(op, MaskOp::Feather(_)))
+        .collect()
+}
+
+// Keyed full resolution alpha of `frame` following the post-processed low resolution alpha `shape`
+// of `shape_frame`: pixels the post-processing settled (speckles and extra people removed, holes
+// filled) take its decision, the edges keep their full resolution key
+fn follow_shape(
+    alpha: &[u8],
+    frame: &Frame,
+    shape: &[u8],
+    shape_frame: &Frame,
+    resizer: &mut Resizer,
+) -> Result<Vec<u8>> {
+    let shape: Vec<f32> = shape.iter().map(|&a| a as f32).collect();
+    let shape = resize_plane(
+        resizer,
+        &shape,
+        shape_frame.width as u32,
+        shape_frame.height as u32,
+        frame.width as u32,
+        frame.height as u32,
+    )?;
+    Ok(alpha
+        .iter()
+        .zip(&shape)
+        .map(|(&a, &s)| {
+            if s < 0.5 {
+                0
+            } else if s > 254.5 {
+                255
+            } else {
+                a
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fast_image_resize::PixelType;
+
+    const GREEN: [u8; 3] = [40, 200, 60];
+
+    fn chroma_key(space: KeySpace) -> ChromaKey {
+        ChromaKey {
+            key: KeyColor::Fixed(GREEN),
+            space,
+            tolerance: 0.25,
+            softness: 0.1,
+            spill: 0.8,
+        }
+    }
+
+    #[test]
+    fn key_color_is_backdrop_and_skin_is_person() {
+        for space in [KeySpace::Hsv, KeySpace::YCbCr] {
+            assert_eq!(pixel_alpha(GREEN, GREEN, &chroma_key(space)), 0, "{:?}", space);
+            assert_eq!(
+                pixel_alpha([220, 170, 140], GREEN, &chroma_key(space)),
+                255,
+                "{:?}",
+                space
+            );
+        }
+    }
+
+    #[test]
+    fn shadows_on_the_backdrop_still_key_in_hsv() {
+        assert_eq!(pixel_alpha([20, 100, 30], GREEN, &chroma_key(KeySpace::Hsv)), 0);
+    }
+
+    #[test]
+    fn spill_is_pulled_down_to_the_other_channels() {
+        let mut px = [100, 180, 90, 255];
+        suppress_pixel_spill(&mut px, GREEN, 1.0);
+        assert_eq!(px, [100, 100, 90, 255]);
+
+        let mut px = [100, 180, 90, 255];
+        suppress_pixel_spill(&mut px, GREEN, 0.5);
+        assert_eq!(px, [100, 140, 90, 255]);
+    }
+
+    #[test]
+    fn full_resolution_alpha_follows_the_settled_shape() {
+        let frame = |size: i32| Frame {
+            width: size,
+            height: size,
+            pixel_type: PixelType::U8x4,
+            data: vec![0; (size * size * 4) as usize],
+        };
+        // Low resolution shape: person on the left half, background on the right half
+        let shape: Vec<u8> = (0..64).map(|i| if i % 8 < 4 { 255 } else { 0 }).collect();
+        // Keyed full resolution alpha: a hole at the far left, a speckle at the far right
+        let mut alpha = vec![128; 16 * 16];
+        alpha[8 * 16] = 0;
+        alpha[8 * 16 + 15] = 255;
+
+        let followed = follow_shape(&alpha, &frame(16), &shape, &frame(8), &mut Resizer::new()).unwrap();
+        assert_eq!(followed[8 * 16], 255, "Hole filled");
+        assert_eq!(followed[8 * 16 + 15], 0, "Speckle removed");
+        assert_eq!(followed[8 * 16 + 8], 128, "Edge keeps its key");
+    }
+
+    #[test]
+    fn border_key_color_ignores_the_middle() {
+        // 5x5 green frame with a red 3x3 person in the middle
+        let data = (0..25)
+            .flat_map(|i| {
+                let (x, y) = (i % 5, i / 5);
+                let inside = (1..4).contains(&x) && (1..4).contains(&y);
+                if inside { [200, 30, 30, 255] } else { [40, 200, 60, 255] }
+            })
+            .collect();
+        let frame = Frame {
+            width: 5,
+            height: 5,
+            pixel_type: PixelType::U8x4,
+            data,
+        };
+        assert_eq!(border_key_color(&frame), GREEN);
+        assert_eq!(pick_key_color(&frame, 2, 2, 1), [200, 30, 30]);
+    }
+}
@@ -0,0 +1,122 @@
+use raylib::prelude::*;
+
+// Fragment shader of the compositing, run with the raylib default vertex shader
+const COMPOSITE_SHADER: &str = include_str!("../shaders/composite.fs");
+
+#[derive(Clone, Copy, Debug)]
+pub struct GpuCompositing {
+    pub feather: f32,        // Feather radius of the upsampled mask edge, in mask pixels
+    pub full_res_mask: bool, // Upload the refined full resolution mask instead of the low resolution one
+}
+
+// What the compositing draws behind the person
+#[derive(Clone, Copy)]
+pub enum Background<'a> {
+    Color(Color),
+    Texture(&'a Texture2D, Rectangle), // Texture and its shown part, in texture pixels
+}
+
+// Uniform locations of the shader
+struct Locations {
+    mask: i32,
+    background: i32,
+    mask_texel: i32,
+    feather: i32,
+    background_mode: i32,
+    background_color: i32,
+    background_rect: i32,
+}
+
+// Composites in a fragment shader from the person, mask and background textures, so that the
+// viewer uploads the frames as they are instead of blending them on the CPU
+pub struct Compositor {
+    pub options: GpuCompositing,
+    shader: Shader,
+    locations: Locations,
+}
+
+impl Compositor {
+    // None when the shader does not compile, the viewer then blends on the CPU
+    pub fn load(rl: &mut RaylibHandle, thread: &RaylibThread, options: GpuCompositing) -> Option<Self> {
+        let shader = rl.load_shader_from_memory(thread, None, Some(COMPOSITE_SHADER));
+        // A shader that fails to build is replaced by the raylib default one, without our uniforms
+        let mask = shader.get_shader_location("mask");
+        if !shader.is_shader_valid() || mask < 0 {
+            eprintln!("Compositing shader unavailable, falling back to CPU compositing");
+            return None;
+        }
+        let locations = Locations {
+            mask,
+            background: shader.get_shader_location("background"),
+            mask_texel: shader.get_shader_location("maskTexel"),
+            feather: shader.get_shader_location("feather"),
+            background_mode: shader.get_shader_location("backgroundMode"),
+            background_color: shader.get_shader_location("backgroundColor"),
+            background_rect: shader.get_shader_location("backgroundRect"),
+        };
+        Some(Self {
+            options,
+            shader,
+            locations,
+        })
+    }
+
+    // Draws the person texture over the background into `rect`, with the alpha of the mask texture
+    // stretched over the same rectangle
+    pub fn draw(
+        &mut self,
+        d: &mut RaylibDrawHandle,
+        person: &Texture2D,
+        mask: &Texture2D,
+        background: Background,
+        rect: Rectangle,
+    ) {
+        let locations = &self.locations;
+        self.shader.set_shader_value(
+            locations.mask_texel,
+            Vector2::new(1.0 / mask.width() as f32, 1.0 / mask.height() as f32),
+        );
+        self.shader.set_shader_value(locations.feather, self.options.feather);
+        match background {
+            Background::Color(color) => {
+                self.shader.set_shader_value(locations.background_mode, 0);
+                self.shader
+                    .set_shader_value(locations.background_color, color.color_normalize());
+            }
+            Background::Texture(texture, source) => {
+                let (width, height) = (texture.width() as f32, texture.height() as f32);
+                self.shader.set_shader_value(locations.background_mode, 1);
+                self.shader.set_shader_value(
+                    locations.background_rect,
+                    Vector4::new(
+                        source.x / width,
+                        source.y / height,
+                        source.width / width,
+                        source.height / height,
+                    ),
+                );
+            }
+        }
+
+        let raw_shader = *self.shader.as_ref();
+        let (mask_location, background_location) = (locations.mask, locations.background);
+        let mut d = d.begin_shader_mode(&mut self.shader);
+        // Texture bindings only last until the next batch flush, which starting the shader mode
+        // does, so they are set while it is active where the safe API cannot reach the shader.
+        // SAFETY: the shader and the textures are loaded and outlive this draw call
+        unsafe {
+            raylib::ffi::SetShaderValueTexture(raw_shader, mask_location, *mask.as_ref());
+            if let Background::Texture(texture, _) = background {
+                raylib::ffi::SetShaderValueTexture(raw_shader, background_location, *texture.as_ref());
+            }
+        }
+        d.draw_texture_pro(
+            person,
+            Rectangle::new(0.0, 0.0, person.width() as f32, person.height() as f32),
+            rect,
+            Vector2::new(0.0, 0.0),
+            0.0,
+            Color::WHITE,
+        );
+    }
+}
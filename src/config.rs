@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Result;
+
+// Runtime settings saved from the viewer panel, as `key = value` lines. They override the `SETUP`
+// defaults on the next start; keys that are missing or do not parse keep the default.
+#[derive(Default)]
+pub struct Config {
+    entries: BTreeMap<String, String>,
+}
+
+impl Config {
+    // Empty when the file does not exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let entries = std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+            .collect();
+        Ok(Self { entries })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut text = String::from("# Saved by the bgremoval viewer, delete to go back to the SETUP defaults\n");
+        for (key, value) in &self.entries {
+            text.push_str(&format!("{} = {}\n", key, value));
+        }
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn set(&mut self, key: &str, value: impl Display) {
+        self.entries.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(String::as_str)
+    }
+
+    // Overwrites `value` with the entry of `key` when present and valid
+    pub fn read<T: FromStr>(&self, key: &str, value: &mut T) {
+        let Some(text) = self.get(key) else {
+            return;
+        };
+        match text.parse() {
+            Ok(parsed) => *value = parsed,
+            Err(_) => eprintln!("Config: invalid {} = {}, default kept", key, text),
+        }
+    }
+
+    pub fn set_rgb(&mut self, key: &str, rgb: [u8; 3]) {
+        self.set(key, format!("{},{},{}", rgb[0], rgb[1], rgb[2]));
+    }
+
+    // RGB written by `set_rgb`
+    pub fn read_rgb(&self, key: &str, rgb: &mut [u8; 3]) {
+        let Some(text) = self.get(key) else {
+            return;
+        };
+        let channels: Vec<u8> = text.split(',').filter_map(|c| c.trim().parse().ok()).collect();
+        match channels[..] {
+            [r, g, b] => *rgb = [r, g, b],
+            _ => eprintln!("Config: invalid {} = {}, default kept", key, text),
+        }
+    }
+}
@@ -11,7 +11,7 @@ use crate::SETUP;
 use crate::bgremoval::MlFrames;
 use crate::viewer::Frame;
 
-pub fn decode(rx: Receiver<Vec<u8>>, ml_tx: Sender<MlFrames>) -> Result<()> {
+pub fn decode(rx: Receiver<Vec<u8>>, ml_tx: Sender<MlFrames>, source: usize) -> Result<()> {
     let mut decompressor = Decompressor::new()?;
     let mut resizer = Resizer::new();
 
@@ -75,6 +75,7 @@ pub fn decode(rx: Receiver<Vec<u8>>, ml_tx: Sender<MlFrames>) -> Result<()> {
         };
 
         ml_tx.send(MlFrames {
+            source,
             high_res_frame: high_res.clone(),
             low_res_frame: low_res.clone(),
             instant: Instant::now(),
@@ -0,0 +1,301 @@
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::Result;
+
+use crate::SETUP;
+use crate::backend;
+use crate::bgremoval::{self, predict_alpha};
+use crate::imageio;
+use crate::postprocess::{self, MaskOp};
+use crate::refine::MaskRefinement;
+
+// Scores of one predicted alpha against its ground truth matte
+#[derive(Clone, Copy, Debug, Default)]
+struct Scores {
+    iou: f32,        // Intersection over union of the masks thresholded at 0.5
+    sad: f32,        // Sum of absolute alpha differences, in thousands
+    mse: f32,        // Mean squared alpha difference
+    gradient: f32,   // Sum of squared gradient magnitude differences, in thousands
+    boundary_f: f32, // F-score of the mask boundaries within a small distance tolerance
+}
+
+impl Scores {
+    const HEADER: &str = "image,iou,sad,mse,gradient,boundary_f";
+
+    fn mean(scores: &[Scores]) -> Scores {
+        let n = scores.len().max(1) as f32;
+        scores.iter().fold(Scores::default(), |sum, s| Scores {
+            iou: sum.iou + s.iou / n,
+            sad: sum.sad + s.sad / n,
+            mse: sum.mse + s.mse / n,
+            gradient: sum.gradient + s.gradient / n,
+            boundary_f: sum.boundary_f + s.boundary_f / n,
+        })
+    }
+
+    fn csv_row(&self, name: &str) -> String {
+        format!(
+            "{},{:.6},{:.6},{:.6},{:.6},{:.6}",
+            name, self.iou, self.sad, self.mse, self.gradient, self.boundary_f
+        )
+    }
+
+    fn json(&self, name: &str) -> String {
+        format!(
+            "{{\"image\": {:?}, \"iou\": {:.6}, \"sad\": {:.6}, \"mse\": {:.6}, \"gradient\": {:.6}, \"boundary_f\": {:.6}}}",
+            name, self.iou, self.sad, self.mse, self.gradient, self.boundary_f
+        )
+    }
+}
+
+// Compares a predicted alpha with the ground truth, both 0-255 at the same resolution
+fn score(predicted: &[u8], truth: &[u8], width: usize, height: usize) -> Scores {
+    let p: Vec<f32> = predicted.iter().map(|&a| a as f32 / 255.0).collect();
+    let g: Vec<f32> = truth.iter().map(|&a| a as f32 / 255.0).collect();
+
+    let (mut intersection, mut union) = (0usize, 0usize);
+    for (&p, &g) in predicted.iter().zip(truth.iter()) {
+        intersection += (p >= 128 && g >= 128) as usize;
+        union += (p >= 128 || g >= 128) as usize;
+    }
+    let sad: f32 = p.iter().zip(g.iter()).map(|(p, g)| (p - g).abs()).sum();
+    let squared: f32 = p.iter().zip(g.iter()).map(|(p, g)| (p - g) * (p - g)).sum();
+    let gradient: f32 = gradient_magnitude(&p, width, height)
+        .iter()
+        .zip(gradient_magnitude(&g, width, height).iter())
+        .map(|(p, g)| (p - g) * (p - g))
+        .sum();
+
+    Scores {
+        iou: if union == 0 {
+            1.0
+        } else {
+            intersection as f32 / union as f32
+        },
+        sad: sad / 1000.0,
+        mse: squared / p.len().max(1) as f32,
+        gradient: gradient / 1000.0,
+        boundary_f: boundary_f_score(predicted, truth, width, height),
+    }
+}
+
+// Central difference gradient magnitude, clamped at the borders
+fn gradient_magnitude(plane: &[f32], width: usize, height: usize) -> Vec<f32> {
+    let at = |x: usize, y: usize| plane[y * width + x];
+    let mut magnitude = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let dx = (at((x + 1).min(width - 1), y) - at(x.saturating_sub(1), y)) * 0.5;
+            let dy = (at(x, (y + 1).min(height - 1)) - at(x, y.saturating_sub(1))) * 0.5;
+            magnitude.push((dx * dx + dy * dy).sqrt());
+        }
+    }
+    magnitude
+}
+
+// Boundary precision/recall F-score, a boundary pixel matches when the other boundary is within
+// 0.75% of the image diagonal (at least one pixel)
+fn boundary_f_score(predicted: &[u8], truth: &[u8], width: usize, height: usize) -> f32 {
+    let tolerance = ((width * width + height * height) as f32).sqrt() * 0.0075;
+    let size = 2 * (tolerance.round() as u32).max(1) + 1;
+
+    let predicted_boundary = boundary(predicted, width, height);
+    let truth_boundary = boundary(truth, width, height);
+    let predicted_near = postprocess::dilate(&predicted_boundary, width, height, size);
+    let truth_near = postprocess::dilate(&truth_boundary, width, height, size);
+
+    // Fraction of the pixels of `boundary` that lie near the other boundary
+    let matched = |boundary: &[u8], near: &[u8]| -> Option<f32> {
+        let count = boundary.iter().filter(|&&b| b > 0).count();
+        let hits = boundary
+            .iter()
+            .zip(near.iter())
+            .filter(|&(&b, &n)| b > 0 && n > 0)
+            .count();
+        (count > 0).then(|| hits as f32 / count as f32)
+    };
+    match (
+        matched(&predicted_boundary, &truth_near),
+        matched(&truth_boundary, &predicted_near),
+    ) {
+        (Some(precision), Some(recall)) if precision + recall > 0.0 => 2.0 * precision * recall / (precision + recall),
+        (None, None) => 1.0, // Neither mask has a boundary
+        _ => 0.0,
+    }
+}
+
+// Pixels of the mask thresholded at 0.5 with a 4-neighbour on the other side (255), 0 elsewhere
+fn boundary(alpha: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let inside = |x: usize, y: usize| alpha[y * width + x] >= 128;
+    let mut edges = vec![0u8; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let here = inside(x, y);
+            let differs = (x > 0 && inside(x - 1, y) != here)
+                || (x + 1 < width && inside(x + 1, y) != here)
+                || (y > 0 && inside(x, y - 1) != here)
+                || (y + 1 < height && inside(x, y + 1) != here);
+            if differs {
+                edges[y * width + x] = 255;
+            }
+        }
+    }
+    edges
+}
+
+// `bgremoval eval <images dir> <mattes dir> [--model path] [--postprocess on|off]
+//  [--refinement upsample|guided|matting] [--csv <output.csv>] [--json <output.json>]`
+// Every JPEG/PNG image is paired with the PNG matte of the same name in the mattes directory.
+pub fn eval_command(args: &[String]) -> Result<()> {
+    let [images_dir, mattes_dir, options @ ..] = args else {
+        return Err(anyhow::anyhow!(
+            "Usage: bgremoval eval <images dir> <mattes dir> [--model path] [--postprocess on|off] [--refinement upsample|guided|matting] [--csv <output.csv>] [--json <output.json>]"
+        ));
+    };
+
+    let mut model = SETUP.model_path.to_string();
+    let mut postprocess_ops: &[MaskOp] = SETUP.mask_postprocess;
+    let mut refinement = SETUP.mask_refinement;
+    let mut csv = None;
+    let mut json = None;
+    for option in options.chunks(2) {
+        match option {
+            [name, value] if name == "--model" => model = value.clone(),
+            [name, value] if name == "--postprocess" && value == "on" => postprocess_ops = SETUP.mask_postprocess,
+            [name, value] if name == "--postprocess" && value == "off" => postprocess_ops = &[],
+            [name, value] if name == "--refinement" && value == "upsample" => refinement = MaskRefinement::Upsample,
+            [name, value] if name == "--refinement" && value == "guided" => {
+                refinement = match SETUP.mask_refinement {
+                    guided @ MaskRefinement::GuidedFilter { .. } => guided,
+                    _ => MaskRefinement::GuidedFilter {
+                        radius: 16,
+                        eps: 1e-3,
+                        subsample: 4,
+                    },
+                }
+            }
+            [name, value] if name == "--refinement" && value == "matting" => {
+                refinement = match SETUP.mask_refinement {
+                    matting @ MaskRefinement::Matting { .. } => matting,
+                    _ => MaskRefinement::Matting {
+                        band: 12,
+                        chroma_key: None,
+                    },
+                }
+            }
+            [name, value] if name == "--csv" => csv = Some(value),
+            [name, value] if name == "--json" => json = Some(value),
+            _ => return Err(anyhow::anyhow!("Unknown option: {:?}", option)),
+        }
+    }
+
+    let pairs = dataset_pairs(Path::new(images_dir), Path::new(mattes_dir))?;
+    println!(
+        "Evaluating {} on {} images, post-processing {:?}, refinement {:?}",
+        model,
+        pairs.len(),
+        postprocess_ops,
+        refinement
+    );
+
+    bgremoval::init_ort()?;
+    let mut backend = backend::load(&model)?;
+
+    let start = Instant::now();
+    let mut results = Vec::with_capacity(pairs.len());
+    println!(
+        "{:<32} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "image", "IoU", "SAD", "MSE", "Grad", "BF"
+    );
+    for (name, image_path, matte_path) in &pairs {
+        let frame = imageio::load_frame(image_path)?;
+        let (truth, width, height) = imageio::load_alpha(matte_path)?;
+        if (width, height) != (frame.width as u32, frame.height as u32) {
+            return Err(anyhow::anyhow!(
+                "{}: matte is {}x{} but the image is {}x{}",
+                name,
+                width,
+                height,
+                frame.width,
+                frame.height
+            ));
+        }
+
+        let predicted = predict_alpha(backend.as_mut(), &frame, postprocess_ops, refinement)?;
+        let scores = score(&predicted, &truth, width as usize, height as usize);
+        println!(
+            "{:<32} {:>8.4} {:>10.3} {:>10.5} {:>10.3} {:>10.4}",
+            name, scores.iou, scores.sad, scores.mse, scores.gradient, scores.boundary_f
+        );
+        results.push((name.clone(), scores));
+    }
+
+    let scores: Vec<Scores> = results.iter().map(|(_, scores)| *scores).collect();
+    let mean = Scores::mean(&scores);
+    println!(
+        "{:<32} {:>8.4} {:>10.3} {:>10.5} {:>10.3} {:>10.4}",
+        "mean", mean.iou, mean.sad, mean.mse, mean.gradient, mean.boundary_f
+    );
+    println!("Evaluated in {} ms", start.elapsed().as_millis());
+
+    if let Some(csv) = csv {
+        let rows: Vec<String> = std::iter::once(Scores::HEADER.to_string())
+            .chain(results.iter().map(|(name, scores)| scores.csv_row(name)))
+            .chain(std::iter::once(mean.csv_row("mean")))
+            .collect();
+        std::fs::write(csv, rows.join("\n") + "\n")?;
+        println!("Scores written to {}", csv);
+    }
+    if let Some(json) = json {
+        let images: Vec<String> = results
+            .iter()
+            .map(|(name, scores)| format!("    {}", scores.json(name)))
+            .collect();
+        std::fs::write(
+            json,
+            format!(
+                "{{\n  \"model\": {:?},\n  \"images\": [\n{}\n  ],\n  \"mean\": {}\n}}\n",
+                model,
+                images.join(",\n"),
+                mean.json("mean")
+            ),
+        )?;
+        println!("Scores written to {}", json);
+    }
+    Ok(())
+}
+
+// (name, image, matte) for every image that has a matte, sorted by name
+fn dataset_pairs(images_dir: &Path, mattes_dir: &Path) -> Result<Vec<(String, PathBuf, PathBuf)>> {
+    let mut pairs = Vec::new();
+    for entry in std::fs::read_dir(images_dir)? {
+        let image_path = entry?.path();
+        let is_image = image_path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ["jpg", "jpeg", "png"].contains(&ext.to_ascii_lowercase().as_str()));
+        let Some(stem) = image_path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if !is_image {
+            continue;
+        }
+
+        let matte_path = mattes_dir.join(format!("{}.png", stem));
+        if matte_path.exists() {
+            pairs.push((stem.to_string(), image_path.clone(), matte_path));
+        } else {
+            eprintln!("No matte for {}, skipped", image_path.display());
+        }
+    }
+    pairs.sort();
+    if pairs.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No image/matte pairs in {} and {}",
+            images_dir.display(),
+            mattes_dir.display()
+        ));
+    }
+    Ok(pairs)
+}
@@ -0,0 +1,106 @@
+use anyhow::Result;
+use fast_image_resize::Resizer;
+
+use crate::refine::{box_filter, resize_plane};
+use crate::viewer::Frame;
+
+// Red, green and blue planes, 0-1
+type Planes = [Vec<f32>; 3];
+
+// Blur-fusion foreground estimation (Forte & Pitié, "Approximate Fast Foreground Colour Estimation"):
+// a coarse pass with a large blur, then a refining pass with a small one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlurFusion {
+    pub radius: u32,        // Blur radius of the first pass in full resolution pixels
+    pub refine_radius: u32, // Blur radius of the second pass, 0 to skip it
+    pub subsample: u32,     // Working resolution divider for the blurs
+}
+
+// Person colors of a frame with its person alpha (255 = person), so that compositing over a new
+// background no longer carries the old background color on the edges. Fully opaque pixels keep
+// their color.
+pub fn estimate_foreground(frame: &Frame, alpha: &[u8], blur_fusion: BlurFusion) -> Result<Frame> {
+    let (width, height) = (frame.width as u32, frame.height as u32);
+    let channels = frame.pixel_type.size();
+    let alpha: Vec<f32> = alpha.iter().map(|&a| a as f32 / 255.0).collect();
+    let image: Planes = [0, 1, 2].map(|c| {
+        frame
+            .data
+            .chunks_exact(channels)
+            .map(|px| px[c] as f32 / 255.0)
+            .collect::<Vec<f32>>()
+    });
+
+    let subsample = blur_fusion.subsample.max(1);
+    let (work_width, work_height) = ((width / subsample).max(1), (height / subsample).max(1));
+    let mut resizer = Resizer::new();
+    let (mut foreground, mut background) = (image.clone(), image.clone());
+    for radius in [blur_fusion.radius, blur_fusion.refine_radius] {
+        if radius == 0 {
+            continue;
+        }
+        // Box blur at the working resolution, brought back to full resolution
+        let work_radius = (radius / subsample).max(1) as usize;
+        let mut blur = |plane: &[f32]| -> Result<Vec<f32>> {
+            let low = resize_plane(&mut resizer, plane, width, height, work_width, work_height)?;
+            let blurred = box_filter(&low, work_width as usize, work_height as usize, work_radius);
+            resize_plane(&mut resizer, &blurred, work_width, work_height, width, height)
+        };
+        (foreground, background) = fusion_pass(&image, &foreground, &background, &alpha, &mut blur)?;
+    }
+
+    let mut estimated = frame.clone();
+    for (i, px) in estimated.data.chunks_exact_mut(channels).enumerate() {
+        for (c, value) in px.iter_mut().take(3).enumerate() {
+            *value = (foreground[c][i].clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    }
+    Ok(estimated)
+}
+
+// One blur-fusion pass: the alpha weighted blurs of the current foreground and background
+// estimates, corrected so that the composite reproduces the image
+fn fusion_pass(
+    image: &Planes,
+    foreground: &Planes,
+    background: &Planes,
+    alpha: &[f32],
+    blur: &mut impl FnMut(&[f32]) -> Result<Vec<f32>>,
+) -> Result<(Planes, Planes)> {
+    let inverse: Vec<f32> = alpha.iter().map(|a| 1.0 - a).collect();
+    let blurred_alpha = blur(alpha)?;
+    let blurred_inverse = blur(&inverse)?;
+
+    let mut blurred_foreground: Planes = Default::default();
+    let mut blurred_background: Planes = Default::default();
+    for c in 0..3 {
+        let weighted: Vec<f32> = foreground[c].iter().zip(alpha).map(|(f, a)| f * a).collect();
+        blurred_foreground[c] = blur(&weighted)?
+            .iter()
+            .zip(&blurred_alpha)
+            .map(|(f, a)| f / (a + 1e-5))
+            .collect();
+        let weighted: Vec<f32> = background[c].iter().zip(&inverse).map(|(b, a)| b * a).collect();
+        blurred_background[c] = blur(&weighted)?
+            .iter()
+            .zip(&blurred_inverse)
+            .map(|(b, a)| b / (a + 1e-5))
+            .collect();
+    }
+
+    let mut new_foreground: Planes = Default::default();
+    let mut new_background: Planes = Default::default();
+    for c in 0..3 {
+        (new_foreground[c], new_background[c]) = (0..alpha.len())
+            .map(|i| {
+                let (a, f, b) = (alpha[i], blurred_foreground[c][i], blurred_background[c][i]);
+                let residual = image[c][i] - a * f - (1.0 - a) * b;
+                (
+                    (f + a * residual).clamp(0.0, 1.0),
+                    (b + (1.0 - a) * residual).clamp(0.0, 1.0),
+                )
+            })
+            .unzip();
+    }
+    Ok((new_foreground, new_background))
+}
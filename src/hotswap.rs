@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::backend::{self, InferenceBackend};
+use crate::bgremoval::Control;
+use anyhow::Result;
+
+// Loads models requested at runtime on a background thread while the running backend keeps
+// serving frames. `backend::load` validates a model with blank runs; a model that fails to load or
+// returns a mask of the wrong size is reported and the running model stays in place.
+pub struct ModelSwap {
+    loaded: Receiver<Box<dyn InferenceBackend>>,
+}
+
+impl ModelSwap {
+    pub fn start(requests: Receiver<PathBuf>) -> Result<Self> {
+        let (loaded_tx, loaded) = std::sync::mpsc::channel();
+        thread::Builder::new().name("model_loader".into()).spawn(move || {
+            while let Ok(path) = requests.recv() {
+                // Only the most recent request matters
+                let path = requests.try_iter().last().unwrap_or(path);
+                println!("Loading model {}...", path.display());
+                match backend::load(&path.to_string_lossy()) {
+                    Ok(backend) => {
+                        if loaded_tx.send(backend).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => eprintln!("Model {} rejected, keeping the running model: {:#}", path.display(), e),
+                }
+            }
+        })?;
+        Ok(Self { loaded })
+    }
+
+    // Replaces the backend with the most recently loaded model, between two inferences.
+    // Returns true when the backend changed.
+    pub fn apply(&self, backend: &mut Box<dyn InferenceBackend>) -> bool {
+        let Some(loaded) = self.loaded.try_iter().last() else {
+            return false;
+        };
+        *backend = loaded;
+        println!("Model swapped");
+        true
+    }
+}
+
+// Requests a reload whenever the model file changes, once its modification time is stable for one
+// interval so a model still being written is not picked up
+pub fn watch_model(path: &'static str, interval: Duration, controls: Sender<Control>) -> Result<()> {
+    let modified = || -> Option<SystemTime> { std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok() };
+    let mut loaded = modified();
+    let mut seen = loaded;
+
+    loop {
+        thread::sleep(interval);
+        let current = modified();
+        if current.is_some() && current == seen && current != loaded {
+            loaded = current;
+            println!("{} changed, reloading", path);
+            if controls.send(Control::LoadModel(PathBuf::from(path))).is_err() {
+                break;
+            }
+        }
+        seen = current;
+    }
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use std::path::Path;
+
+use anyhow::Result;
+use fast_image_resize::PixelType;
+use raylib::prelude::*;
+use raylib::texture::Image;
+use turbojpeg::PixelFormat;
+
+use crate::viewer::Frame;
+
+// Loads a still image as an RGBA frame: JPEG through turbojpeg, other formats (PNG) through raylib
+pub fn load_frame(path: &Path) -> Result<Frame> {
+    let is_jpeg = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg"));
+
+    if is_jpeg {
+        let image = turbojpeg::decompress(&std::fs::read(path)?, PixelFormat::RGBA)?;
+        return Ok(Frame {
+            width: image.width as i32,
+            height: image.height as i32,
+            pixel_type: PixelType::U8x4,
+            data: image.pixels,
+        });
+    }
+
+    let image = Image::load_image(&path.to_string_lossy())
+        .map_err(|e| anyhow::anyhow!("Failed to load {}: {}", path.display(), e))?;
+    let data = image
+        .get_image_data()
+        .iter()
+        .flat_map(|c| [c.r, c.g, c.b, c.a])
+        .collect();
+    Ok(Frame {
+        width: image.width(),
+        height: image.height(),
+        pixel_type: PixelType::U8x4,
+        data,
+    })
+}
+
+// Loads a single channel matte (e.g. a ground truth alpha), taking the first channel of the image
+pub fn load_alpha(path: &Path) -> Result<(Vec<u8>, u32, u32)> {
+    let frame = load_frame(path)?;
+    let alpha = frame.data.chunks_exact(4).map(|px| px[0]).collect();
+    Ok((alpha, frame.width as u32, frame.height as u32))
+}
+
+// Saves an RGBA frame as PNG
+pub fn save_png(path: &Path, frame: &Frame) -> Result<()> {
+    let rgba = frame.as_rgba();
+    let image = Image::gen_image_color(frame.width, frame.height, Color::BLANK);
+    // SAFETY: gen_image_color allocates an uncompressed R8G8B8A8 buffer of width * height pixels
+    unsafe {
+        std::ptr::copy_nonoverlapping(rgba.as_ptr(), image.data() as *mut u8, rgba.len());
+    }
+    // export_image only logs its failures, so the file is checked instead, with any previous one
+    // removed first so that it cannot pass for the new one
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(anyhow::anyhow!("Failed to replace {}: {}", path.display(), e)),
+    }
+    image.export_image(&path.to_string_lossy());
+    if !path.exists() {
+        return Err(anyhow::anyhow!("Failed to write {}", path.display()));
+    }
+    Ok(())
+}
+
+// Saves a single channel alpha as a grayscale PNG
+pub fn save_alpha_png(path: &Path, alpha: &[u8], width: u32, height: u32) -> Result<()> {
+    save_png(
+        path,
+        &Frame {
+            width: width as i32,
+            height: height as i32,
+            pixel_type: PixelType::U8x4,
+            data: alpha.iter().flat_map(|&a| [a, a, a, 255]).collect(),
+        },
+    )
+}
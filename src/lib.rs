@@ -0,0 +1,148 @@
+pub mod backend;
+pub mod bench;
+pub mod bgremoval;
+pub mod capture;
+pub mod chroma;
+pub mod compositor;
+pub mod config;
+pub mod decoder;
+pub mod eval;
+pub mod foreground;
+pub mod hotswap;
+pub mod imageio;
+pub mod matting;
+pub mod motion;
+pub mod overlay;
+pub mod panel;
+pub mod plate;
+pub mod postprocess;
+pub mod refine;
+pub mod roi;
+pub mod tiling;
+pub mod viewer;
+
+use crate::bgremoval::{InferenceCadence, OptimizationLevel, Provider, Segmentation, SessionOptions};
+use crate::compositor::GpuCompositing;
+use crate::foreground::BlurFusion;
+use crate::postprocess::MaskOp;
+use crate::refine::MaskRefinement;
+use crate::roi::RoiTracking;
+use crate::tiling::Tiling;
+use crate::viewer::{BackgroundMode, CameraLayout, ViewLayout};
+use fast_image_resize::PixelType;
+use std::time::Duration;
+
+pub static SETUP: Setup = Setup {
+    cameras: &[Camera {
+        device: 0,         // Default to first camera
+        label: "Camera 0", // Shown in the viewer
+    }], // One capture and decode chain per camera, all sharing the inference stage
+    camera_layout: CameraLayout::Switchable, // Or Tiled to show all cameras at once
+    view_layout: ViewLayout::DebugGrid,      // Or Composite / SideBySide / MaskOverlay, V switches while running
+    background_mode: BackgroundMode::Color,  // Or Blur / Image, G switches while running
+    background_color: [0, 0, 0],             // RGB behind the person in Color mode
+    background_images: Some("backgrounds"),  // Pictures (JPEG/PNG) for the Image background, None to disable
+    gpu_compositing: Some(GpuCompositing {
+        feather: 1.0,         // Softens the upsampled mask edge, in mask pixels
+        full_res_mask: false, // Upsample the low resolution mask on the GPU, true for the refined full one
+    }), // Composite in a fragment shader, None to blend on the CPU
+    capture_width: 1920,                     // Default width
+    capture_res_height: 1080,                // Default height
+    full_dec_width: 1920,                    // Width for high resolution
+    full_dec_height: 1080,                   // Height for high resolution
+    ful_dec_pixel_type: PixelType::U8x4,     // Pixel type for high
+    small_dec_width: 512,                    // Width for low resolution
+    small_dec_height: 512,                   // Height for low resolution
+    small_dec_pixel_type: PixelType::U8x4,   // Pixel type for low resolution
+    segmentation: Segmentation::Model,       // Or Segmentation::ChromaKey for green screen setups
+    mask_threshold: 235,                     // Model person probability (0-255) above which a pixel is person
+    model_path: "models/model.onnx",         // ONNX model or stub:ellipse / stub:brightness, low resolution input
+    model_watch: Some(Duration::from_secs(2)), // Reload the model when the file changes, None to disable
+    settings_path: "bgremoval.conf",         // Settings saved from the viewer panel, over these defaults
+    session: SessionOptions {
+        providers: &[
+            Provider::TensorRt {
+                engine_cache: Some("models/trt_cache"), // First build can take minutes
+                fp16: true,
+                int8: false,
+                workspace_size: 2 << 30, // 2 GiB
+            },
+            Provider::Cuda,
+            Provider::Cpu,
+        ], // Falls back to the next provider when one fails to load or run the model
+        intra_threads: 0, // ONNX Runtime default
+        inter_threads: 0, // ONNX Runtime default
+        optimization_level: OptimizationLevel::Level3,
+        optimized_model_cache: None, // e.g. Some("models/model.optimized.onnx")
+        profiling: None,             // e.g. Some("profiles/bgremoval")
+        warmup_runs: 3,              // Absorb lazy CUDA initialization before the first frame
+    },
+    mask_refinement: MaskRefinement::GuidedFilter {
+        radius: 16,   // Guided filter window radius at full resolution
+        eps: 1e-3,    // Guided filter regularization
+        subsample: 4, // Fast guided filter subsampling factor
+    }, // Use MaskRefinement::Upsample for plain upsampling
+    foreground_estimation: None, // Some(BlurFusion { .. }) removes the old background color from the edges
+    mask_postprocess: &[
+        MaskOp::Open(3),        // Remove background speckles
+        MaskOp::Close(5),       // Close small gaps in the person
+        MaskOp::FillHoles,      // Fill holes enclosed by the person
+        MaskOp::KeepLargest(2), // Keep at most two people
+        MaskOp::Feather(1),     // Soften the edge before upsampling
+    ], // Applied in order on the low resolution mask, use &[] to disable
+    temporal_smoothing: 0.0,     // Weight of the previous mask in the shown mask, 0 to disable
+    inference_cadence: InferenceCadence::Adaptive {
+        motion_threshold: 2.0, // Mean luma difference since the last inference
+        max_skip: 4,           // Never reuse a mask for more than 4 frames
+    }, // Or EveryFrame, EveryNth(n), TargetFps(fps)
+    async_inference: false,      // Composite every camera frame with the latest mask while inference runs aside
+    motion_compensation: true,   // Warp reused masks to follow motion since the inferred frame
+    roi_tracking: Some(RoiTracking {
+        margin: 0.15,         // Margin around the person box
+        min_area: 0.01,       // Fall back to the full frame below 1% of the frame
+        refresh_interval: 30, // Run on the full frame every 30 inferences to catch new people
+    }), // None to always run on the full frame
+    tiling: Tiling {
+        tile_size: 512, // Tile side for `bgremoval tile`, the model input size so tiles are not downscaled
+        overlap: 128,   // Overlap blended between tiles
+        max_batch: 4,   // Tiles per run when the model accepts a dynamic batch
+    },
+};
+
+pub struct Camera {
+    pub device: usize,
+    pub label: &'static str,
+}
+
+pub struct Setup {
+    pub cameras: &'static [Camera],
+    pub camera_layout: CameraLayout,
+    pub view_layout: ViewLayout,
+    pub background_mode: BackgroundMode,
+    pub background_color: [u8; 3],
+    pub background_images: Option<&'static str>,
+    pub gpu_compositing: Option<GpuCompositing>,
+    pub capture_width: u32,
+    pub capture_res_height: u32,
+    pub full_dec_width: u32,
+    pub full_dec_height: u32,
+    pub ful_dec_pixel_type: PixelType,
+    pub small_dec_width: u32,
+    pub small_dec_height: u32,
+    pub small_dec_pixel_type: PixelType,
+    pub segmentation: Segmentation,
+    pub mask_threshold: u8,
+    pub model_path: &'static str,
+    pub model_watch: Option<Duration>,
+    pub settings_path: &'static str,
+    pub session: SessionOptions,
+    pub mask_refinement: MaskRefinement,
+    pub foreground_estimation: Option<BlurFusion>,
+    pub mask_postprocess: &'static [MaskOp],
+    pub temporal_smoothing: f32,
+    pub inference_cadence: InferenceCadence,
+    pub async_inference: bool,
+    pub motion_compensation: bool,
+    pub roi_tracking: Option<RoiTracking>,
+    pub tiling: Tiling,
+}
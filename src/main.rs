@@ -1,14 +1,9 @@
-pub mod bgremoval;
-pub mod capture;
-pub mod decoder;
-pub mod viewer;
-
-use crate::bgremoval::MlFrames;
-use crate::capture::capture;
-use crate::decoder::decode;
-use crate::viewer::RaylibFrames;
 use anyhow::Result;
-use fast_image_resize::PixelType;
+use bgremoval::bgremoval::MlFrames;
+use bgremoval::capture::{CameraControls, capture};
+use bgremoval::decoder::decode;
+use bgremoval::viewer::RaylibFrames;
+use bgremoval::{Camera, SETUP, bench, eval, hotswap, tiling, viewer};
 use std::any::Any;
 use std::thread;
 use v4l::Device;
@@ -19,31 +14,17 @@ use v4l::context::enum_devices;
 use v4l::prelude::MmapStream;
 use v4l::video::Capture;
 
-pub static SETUP: Setup = Setup {
-    camera_device: 0,                      // Default to first camera
-    capture_width: 1920,                   // Default width
-    capture_res_height: 1080,              // Default height
-    full_dec_width: 1920,                  // Width for high resolution
-    full_dec_height: 1080,                 // Height for high resolution
-    ful_dec_pixel_type: PixelType::U8x4,   // Pixel type for high
-    small_dec_width: 512,                  // Width for low resolution
-    small_dec_height: 512,                 // Height for low resolution
-    small_dec_pixel_type: PixelType::U8x4, // Pixel type for low resolution
-};
-
-pub struct Setup {
-    camera_device: usize,
-    capture_width: u32,
-    capture_res_height: u32,
-    full_dec_width: u32,
-    full_dec_height: u32,
-    ful_dec_pixel_type: PixelType,
-    small_dec_width: u32,
-    small_dec_height: u32,
-    small_dec_pixel_type: PixelType,
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("bench") => bench::bench_command(&args[2..]),
+        Some("eval") => eval::eval_command(&args[2..]),
+        Some("tile") => tiling::tile_command(&args[2..]),
+        _ => run_pipeline(),
+    }
 }
 
-fn main() -> Result<()> {
+fn run_pipeline() -> Result<()> {
     println!("Starting camera stream...");
 
     // List all present devices
@@ -51,58 +32,57 @@ fn main() -> Result<()> {
         println!("Found device: {:?}, {:?}, {:?}", dev.path(), dev.name(), dev.type_id());
     }
 
-    let dev = match Device::new(SETUP.camera_device) {
-        Ok(device) => device,
-        Err(e) => {
-            eprintln!("Failed to open device: {:#?}", e);
-            return Err(e.into());
-        }
-    };
     let mut join_handles = Vec::new();
-
-    println!("=== Supported Formats ===");
-    for format in dev.enum_formats()? {
-        println!("Pixel format: {}, description: {}", format.fourcc, format.description);
-
-        for size in dev.enum_framesizes(format.fourcc)? {
-            println!("  {:?}", size);
-        }
-    }
-
-    let fmt = Format::new(SETUP.capture_width, SETUP.capture_res_height, FourCC::new(b"MJPG"));
-    dev.set_format(&fmt)?;
-
-    let stream = MmapStream::with_buffers(&dev, Type::VideoCapture, 4)?;
-    println!("Selected format: {:?}", fmt);
-    println!("Starting video capture...");
-
-    let (tx, rx) = std::sync::mpsc::channel();
     let (ml_tx, ml_rx) = std::sync::mpsc::channel::<MlFrames>();
-    let (raylib_tx, raylib_rx) = std::sync::mpsc::channel::<RaylibFrames>();
-
-    println!("Starting capture...");
-
-    join_handles.push(
-        thread::Builder::new()
-            .name("capture".into())
-            .spawn(move || -> Result<()> { capture(tx, stream) })?,
-    );
-    join_handles.push(
+    let mut outputs = Vec::new();
+    let mut streams = Vec::new();
+    let mut cameras = Vec::new();
+
+    for (source, camera) in SETUP.cameras.iter().enumerate() {
+        let stream = open_camera(camera)?;
+        println!("Starting capture of {}...", camera.label);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (raylib_tx, raylib_rx) = std::sync::mpsc::channel::<RaylibFrames>();
+        let ml_tx = ml_tx.clone();
+        outputs.push(raylib_tx);
+        streams.push((camera.label, raylib_rx));
+        cameras.push(CameraControls::open(camera.device));
+
+        join_handles.push(
+            thread::Builder::new()
+                .name(format!("capture-{}", source))
+                .spawn(move || -> Result<()> { capture(tx, stream) })?,
+        );
+        join_handles.push(
+            thread::Builder::new()
+                .name(format!("decoder-{}", source))
+                .spawn(move || -> Result<()> { decode(rx, ml_tx, source) })?,
+        );
+    }
+    // Inference stops once every decoder is gone
+    drop(ml_tx);
+
+    // Requests to the inference stage, from the model file watcher and the viewer
+    let (control_tx, control_rx) = std::sync::mpsc::channel();
+    if let Some(interval) = SETUP.model_watch {
+        let control_tx = control_tx.clone();
+        // Not joined: polls until the process exits
         thread::Builder::new()
-            .name("decoder".into())
-            .spawn(move || -> Result<()> { decode(rx, ml_tx) })?,
-    );
+            .name("model_watch".into())
+            .spawn(move || -> Result<()> { hotswap::watch_model(SETUP.model_path, interval, control_tx) })?;
+    }
 
     join_handles.push(
         thread::Builder::new()
             .name("bgremoval".into())
-            .spawn(move || -> Result<()> { bgremoval::bgremoval(ml_rx, raylib_tx) })?,
+            .spawn(move || -> Result<()> { bgremoval::bgremoval::bgremoval(ml_rx, outputs, control_rx) })?,
     );
 
     join_handles.push(
         thread::Builder::new()
             .name("raylib_viewer".into())
-            .spawn(move || -> Result<()> { viewer::start_raylib_viewer(raylib_rx) })?,
+            .spawn(move || -> Result<()> { viewer::start_raylib_viewer(streams, control_tx, cameras) })?,
     );
 
     for handle in join_handles {
@@ -115,3 +95,30 @@ fn main() -> Result<()> {
     }
     Ok(())
 }
+
+// Opens the camera device in MJPG at the capture resolution
+fn open_camera(camera: &Camera) -> Result<MmapStream<'static>> {
+    let dev = match Device::new(camera.device) {
+        Ok(device) => device,
+        Err(e) => {
+            eprintln!("Failed to open device {} ({}): {:#?}", camera.device, camera.label, e);
+            return Err(e.into());
+        }
+    };
+
+    println!("=== Supported Formats of {} ===", camera.label);
+    for format in dev.enum_formats()? {
+        println!("Pixel format: {}, description: {}", format.fourcc, format.description);
+
+        for size in dev.enum_framesizes(format.fourcc)? {
+            println!("  {:?}", size);
+        }
+    }
+
+    let fmt = Format::new(SETUP.capture_width, SETUP.capture_res_height, FourCC::new(b"MJPG"));
+    dev.set_format(&fmt)?;
+
+    let stream = MmapStream::with_buffers(&dev, Type::VideoCapture, 4)?;
+    println!("Selected format: {:?}", fmt);
+    Ok(stream)
+}
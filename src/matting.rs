@@ -0,0 +1,236 @@
+use crate::chroma::{self, ChromaKey, KeyColor};
+use crate::postprocess;
+use crate::viewer::Frame;
+
+pub const BACKGROUND: u8 = 0;
+pub const UNKNOWN: u8 = 128;
+pub const FOREGROUND: u8 = 255;
+
+// Rays cast from every unknown pixel to collect foreground and background samples
+const RAYS: usize = 8;
+// Weight of the sample distance, in band widths, against the color distortion (0-1 per channel)
+const DISTANCE_WEIGHT: f32 = 0.02;
+
+// Full resolution alpha matte and foreground colors
+pub struct Matte {
+    pub alpha: Vec<u8>,    // 255 = person
+    pub foreground: Frame, // Camera frame with the unknown band replaced by the estimated person colors
+}
+
+// Foreground/background candidate pair chosen for an unknown pixel
+#[derive(Clone, Copy)]
+struct Pair {
+    foreground: [f32; 3],
+    background: [f32; 3],
+}
+
+// Trimap of a low resolution person alpha: foreground inside the mask eroded by `band` pixels,
+// background outside the mask dilated by `band` pixels, unknown in between
+pub fn trimap(alpha: &[u8], width: usize, height: usize, band: u32) -> Vec<u8> {
+    let binary: Vec<u8> = alpha.iter().map(|&a| if a >= 128 { 255 } else { 0 }).collect();
+    let size = 2 * band.max(1) + 1;
+    let inner = postprocess::erode(&binary, width, height, size);
+    let outer = postprocess::dilate(&binary, width, height, size);
+    inner
+        .iter()
+        .zip(outer.iter())
+        .map(|(&inner, &outer)| match (inner, outer) {
+            (255, _) => FOREGROUND,
+            (_, 0) => BACKGROUND,
+            _ => UNKNOWN,
+        })
+        .collect()
+}
+
+// Nearest neighbour upsampling of a trimap to the frame resolution
+fn upsample_trimap(trimap: &[u8], width: usize, height: usize, full_width: usize, full_height: usize) -> Vec<u8> {
+    let mut full = Vec::with_capacity(full_width * full_height);
+    for y in 0..full_height {
+        let row = &trimap[(y * height / full_height) * width..][..width];
+        full.extend((0..full_width).map(|x| row[x * width / full_width]));
+    }
+    full
+}
+
+// Refines the low resolution model alpha into a full resolution matte. The trimap is built at low
+// resolution with a band of `band` full resolution pixels (at least) on each side of the mask edge,
+// and only its unknown band is solved on the full resolution frame:
+// - without a chroma key, by shared sampling matting: each unknown pixel casts rays to the nearest
+//   definite foreground and background colors and takes the pair that best explains its color, then
+//   picks the best pair among its neighbours' choices
+// - with a chroma key, by keying the band against the backdrop, with spill suppression on the
+//   foreground colors. `KeyColor::Border` uses the median color of the definite background.
+pub fn matte(
+    frame: &Frame,
+    alpha: &[u8],
+    alpha_width: u32,
+    alpha_height: u32,
+    band: u32,
+    chroma_key: Option<ChromaKey>,
+) -> Matte {
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let (alpha_width, alpha_height) = (alpha_width as usize, alpha_height as usize);
+    let low_band = (band as usize * alpha_height).div_ceil(height).max(1) as u32;
+    let trimap = upsample_trimap(
+        &trimap(alpha, alpha_width, alpha_height, low_band),
+        alpha_width,
+        alpha_height,
+        width,
+        height,
+    );
+
+    let channels = frame.pixel_type.size();
+    let mut matte_alpha: Vec<u8> = trimap.iter().map(|&t| if t == FOREGROUND { 255 } else { 0 }).collect();
+    let mut foreground = frame.clone();
+    let unknown: Vec<usize> = (0..width * height).filter(|&i| trimap[i] == UNKNOWN).collect();
+
+    if let Some(chroma_key) = chroma_key {
+        let key = match chroma_key.key {
+            KeyColor::Fixed(rgb) => rgb,
+            KeyColor::Border => background_median(frame, &trimap),
+        };
+        for &i in &unknown {
+            let px = &mut foreground.data[i * channels..i * channels + 3];
+            matte_alpha[i] = chroma::pixel_alpha([px[0], px[1], px[2]], key, &chroma_key);
+            if chroma_key.spill > 0.0 {
+                chroma::suppress_pixel_spill(px, key, chroma_key.spill);
+            }
+        }
+        return Matte {
+            alpha: matte_alpha,
+            foreground,
+        };
+    }
+
+    let color = |i: usize| -> [f32; 3] {
+        let px = &frame.data[i * channels..];
+        [px[0] as f32 / 255.0, px[1] as f32 / 255.0, px[2] as f32 / 255.0]
+    };
+    let max_walk = 4 * band.max(1) as usize;
+
+    // Sampling: the best pair along the rays of each unknown pixel, the ray directions rotate from
+    // pixel to pixel so that neighbours see different samples
+    let mut pairs: Vec<Option<Pair>> = vec![None; width * height];
+    for &i in &unknown {
+        let (x, y) = (i % width, i / width);
+        let rotation = ((x % 3) * 3 + y % 3) as f32 / 9.0;
+        let mut foregrounds = Vec::with_capacity(RAYS);
+        let mut backgrounds = Vec::with_capacity(RAYS);
+        for ray in 0..RAYS {
+            let angle = (ray as f32 + rotation) * std::f32::consts::TAU / RAYS as f32;
+            let (dx, dy) = (angle.cos(), angle.sin());
+            let (mut found_foreground, mut found_background) = (false, false);
+            for step in 1..=max_walk {
+                let sx = (x as f32 + dx * step as f32).round();
+                let sy = (y as f32 + dy * step as f32).round();
+                if sx < 0.0 || sy < 0.0 || sx >= width as f32 || sy >= height as f32 {
+                    break;
+                }
+                let s = sy as usize * width + sx as usize;
+                let distance = step as f32 / band.max(1) as f32;
+                match trimap[s] {
+                    FOREGROUND if !found_foreground => {
+                        foregrounds.push((color(s), distance));
+                        found_foreground = true;
+                    }
+                    BACKGROUND if !found_background => {
+                        backgrounds.push((color(s), distance));
+                        found_background = true;
+                    }
+                    _ => {}
+                }
+                if found_foreground && found_background {
+                    break;
+                }
+            }
+        }
+
+        let c = color(i);
+        pairs[i] = foregrounds
+            .iter()
+            .flat_map(|&(f, df)| backgrounds.iter().map(move |&(b, db)| (f, b, df + db)))
+            .map(|(f, b, distance)| {
+                let pair = Pair {
+                    foreground: f,
+                    background: b,
+                };
+                (pair, distortion(c, pair) + DISTANCE_WEIGHT * distance)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(pair, _)| pair);
+    }
+
+    // Gathering: the pair among the 3x3 neighbourhood that best explains the pixel color
+    for &i in &unknown {
+        let (x, y) = (i % width, i / width);
+        let c = color(i);
+        let best = (y.saturating_sub(1)..(y + 2).min(height))
+            .flat_map(|ny| (x.saturating_sub(1)..(x + 2).min(width)).map(move |nx| ny * width + nx))
+            .filter_map(|n| pairs[n])
+            .min_by(|a, b| distortion(c, *a).total_cmp(&distortion(c, *b)));
+
+        let (a, f) = match best {
+            Some(pair) => {
+                let a = pair_alpha(c, pair);
+                // Solves c = a * f + (1 - a) * b for f, the sampled foreground color when alpha is low
+                let f = if a > 0.1 {
+                    [0, 1, 2].map(|k| ((c[k] - (1.0 - a) * pair.background[k]) / a).clamp(0.0, 1.0))
+                } else {
+                    pair.foreground
+                };
+                (a, f)
+            }
+            // No foreground and background within reach, keep the model alpha
+            None => (
+                alpha[(y * alpha_height / height) * alpha_width + x * alpha_width / width] as f32 / 255.0,
+                c,
+            ),
+        };
+        matte_alpha[i] = (a * 255.0).round() as u8;
+        for (k, value) in f.iter().enumerate() {
+            foreground.data[i * channels + k] = (value * 255.0).round() as u8;
+        }
+    }
+
+    Matte {
+        alpha: matte_alpha,
+        foreground,
+    }
+}
+
+// Alpha of the color `c` projected onto the line between the background and foreground colors
+fn pair_alpha(c: [f32; 3], pair: Pair) -> f32 {
+    let fb: [f32; 3] = [0, 1, 2].map(|k| pair.foreground[k] - pair.background[k]);
+    let length = fb.iter().map(|v| v * v).sum::<f32>();
+    if length < 1e-6 {
+        return 0.5;
+    }
+    ((0..3).map(|k| (c[k] - pair.background[k]) * fb[k]).sum::<f32>() / length).clamp(0.0, 1.0)
+}
+
+// Distance between the color `c` and its reconstruction from the pair
+fn distortion(c: [f32; 3], pair: Pair) -> f32 {
+    let a = pair_alpha(c, pair);
+    (0..3)
+        .map(|k| {
+            let d = c[k] - (a * pair.foreground[k] + (1.0 - a) * pair.background[k]);
+            d * d
+        })
+        .sum::<f32>()
+        .sqrt()
+}
+
+// Per channel median of the definite background of the trimap, sampled on a grid
+fn background_median(frame: &Frame, trimap: &[u8]) -> [u8; 3] {
+    let channels = frame.pixel_type.size();
+    let mut samples: [Vec<u8>; 3] = Default::default();
+    for i in (0..trimap.len()).step_by(7).filter(|&i| trimap[i] == BACKGROUND) {
+        for (channel, &value) in samples.iter_mut().zip(&frame.data[i * channels..i * channels + 3]) {
+            channel.push(value);
+        }
+    }
+    samples.map(|mut channel| {
+        channel.sort_unstable();
+        channel.get(channel.len() / 2).copied().unwrap_or(0)
+    })
+}
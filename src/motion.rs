@@ -0,0 +1,215 @@
+use fast_image_resize::PixelType;
+
+use crate::viewer::Frame;
+
+// Luma of every `step`-th pixel in both directions, returns (luma, width, height)
+pub fn sampled_luma(frame: &Frame, step: usize) -> (Vec<u8>, usize, usize) {
+    let channels = match frame.pixel_type {
+        PixelType::U8x3 => 3,
+        PixelType::U8x4 => 4,
+        _ => panic!("Unsupported pixel type: {:?}", frame.pixel_type),
+    };
+    let step = step.max(1);
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    let out_width = width.div_ceil(step);
+    let out_height = height.div_ceil(step);
+
+    let mut luma = Vec::with_capacity(out_width * out_height);
+    for y in (0..height).step_by(step) {
+        for x in (0..width).step_by(step) {
+            let px = &frame.data[(y * width + x) * channels..];
+            luma.push(((77 * px[0] as u32 + 150 * px[1] as u32 + 29 * px[2] as u32) >> 8) as u8);
+        }
+    }
+    (luma, out_width, out_height)
+}
+
+// Mean absolute luma difference (0-255) between two sampled frames
+pub fn mean_abs_diff(a: &[u8], b: &[u8]) -> f32 {
+    if a.is_empty() || a.len() != b.len() {
+        return f32::MAX;
+    }
+    let sum: u64 = a.iter().zip(b.iter()).map(|(&a, &b)| a.abs_diff(b) as u64).sum();
+    sum as f32 / a.len() as f32
+}
+
+// Per block displacement between two sampled luma frames. Vectors point from a block
+// of the current frame to where its content was in the reference frame.
+pub struct MotionField {
+    pub blocks_x: usize,
+    pub blocks_y: usize,
+    pub block_size: usize,
+    pub vectors: Vec<(f32, f32)>,
+}
+
+impl MotionField {
+    // Cost added per pixel of displacement, favours small vectors on ambiguous blocks
+    const MOTION_PENALTY: u32 = 4;
+
+    // Block matching with a full search of +-`search` pixels, minimizing the sum of absolute differences
+    pub fn estimate(
+        reference: &[u8],
+        current: &[u8],
+        width: usize,
+        height: usize,
+        block_size: usize,
+        search: i32,
+    ) -> Self {
+        let blocks_x = width.div_ceil(block_size);
+        let blocks_y = height.div_ceil(block_size);
+        let mut vectors = Vec::with_capacity(blocks_x * blocks_y);
+
+        for by in 0..blocks_y {
+            for bx in 0..blocks_x {
+                let x0 = bx * block_size;
+                let y0 = by * block_size;
+                let x1 = (x0 + block_size).min(width);
+                let y1 = (y0 + block_size).min(height);
+
+                let sad = |dx: i32, dy: i32| -> Option<u32> {
+                    if (x0 as i32 + dx) < 0
+                        || (y0 as i32 + dy) < 0
+                        || (x1 as i32 + dx) > width as i32
+                        || (y1 as i32 + dy) > height as i32
+                    {
+                        return None;
+                    }
+                    let mut sum = 0u32;
+                    for y in y0..y1 {
+                        let ry = (y as i32 + dy) as usize;
+                        for x in x0..x1 {
+                            let rx = (x as i32 + dx) as usize;
+                            sum += current[y * width + x].abs_diff(reference[ry * width + rx]) as u32;
+                        }
+                    }
+                    Some(sum)
+                };
+
+                // Start from no motion so flat blocks don't pick random vectors
+                let mut best = (0, 0);
+                let mut best_cost = sad(0, 0).unwrap_or(u32::MAX);
+                for dy in -search..=search {
+                    for dx in -search..=search {
+                        let Some(cost) = sad(dx, dy) else {
+                            continue;
+                        };
+                        let cost = cost + (dx.unsigned_abs() + dy.unsigned_abs()) * Self::MOTION_PENALTY;
+                        if cost < best_cost {
+                            best_cost = cost;
+                            best = (dx, dy);
+                        }
+                    }
+                }
+                vectors.push((best.0 as f32, best.1 as f32));
+            }
+        }
+
+        Self {
+            blocks_x,
+            blocks_y,
+            block_size,
+            vectors,
+        }
+    }
+
+    // Displacement at a sampled frame position, bilinearly interpolated between block centers
+    pub fn at(&self, x: f32, y: f32) -> (f32, f32) {
+        let fx = (x / self.block_size as f32 - 0.5).clamp(0.0, (self.blocks_x - 1) as f32);
+        let fy = (y / self.block_size as f32 - 0.5).clamp(0.0, (self.blocks_y - 1) as f32);
+        let x0 = fx.floor() as usize;
+        let y0 = fy.floor() as usize;
+        let x1 = (x0 + 1).min(self.blocks_x - 1);
+        let y1 = (y0 + 1).min(self.blocks_y - 1);
+        let tx = fx - x0 as f32;
+        let ty = fy - y0 as f32;
+
+        let v = |bx: usize, by: usize| self.vectors[by * self.blocks_x + bx];
+        let lerp = |a: (f32, f32), b: (f32, f32), t: f32| (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+        lerp(lerp(v(x0, y0), v(x1, y0), tx), lerp(v(x0, y1), v(x1, y1), tx), ty)
+    }
+}
+
+// Moves the mask of the reference frame to the current frame. `scale` converts
+// mask pixels to the sampled frame pixels the field was estimated on.
+pub fn warp_mask(alpha: &[u8], width: usize, height: usize, field: &MotionField, scale: f32) -> Vec<u8> {
+    let mut warped = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let (dx, dy) = field.at(x as f32 * scale, y as f32 * scale);
+            let sx = (x as f32 + dx / scale).round().clamp(0.0, (width - 1) as f32) as usize;
+            let sy = (y as f32 + dy / scale).round().clamp(0.0, (height - 1) as f32) as usize;
+            warped.push(alpha[sy * width + sx]);
+        }
+    }
+    warped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(blocks_x: usize, blocks_y: usize, block_size: usize, vector: (f32, f32)) -> MotionField {
+        MotionField {
+            blocks_x,
+            blocks_y,
+            block_size,
+            vectors: vec![vector; blocks_x * blocks_y],
+        }
+    }
+
+    // Luma without repeating patterns, so block matching has a single answer
+    fn texture(width: usize, height: usize) -> Vec<u8> {
+        (0..width * height)
+            .map(|i| ((i as u32).wrapping_mul(2654435761) >> 24) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn zero_motion_maps_every_pixel_to_itself() {
+        let alpha: Vec<u8> = (0..64).map(|i| (i * 4) as u8).collect();
+        assert_eq!(warp_mask(&alpha, 8, 8, &field(2, 2, 4, (0.0, 0.0)), 1.0), alpha);
+    }
+
+    #[test]
+    fn uniform_motion_shifts_the_mask() {
+        let alpha: Vec<u8> = (0..64).map(|i| (i * 4) as u8).collect();
+        let warped = warp_mask(&alpha, 8, 8, &field(2, 2, 4, (2.0, 0.0)), 1.0);
+        // Each pixel takes the value two to its right, clamped at the border
+        assert_eq!(warped[3 * 8 + 1], alpha[3 * 8 + 3]);
+        assert_eq!(warped[3 * 8 + 7], alpha[3 * 8 + 7]);
+    }
+
+    #[test]
+    fn estimate_finds_a_known_shift() {
+        let (width, height) = (32, 32);
+        let reference = texture(width, height);
+        // The current frame shows the reference content moved 3 pixels left and 1 up
+        let current: Vec<u8> = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width + 3).min(width - 1), (i / width + 1).min(height - 1));
+                reference[y * width + x]
+            })
+            .collect();
+        let field = MotionField::estimate(&reference, &current, width, height, 8, 4);
+        // Blocks whose displaced position stays inside the frame
+        for by in 0..3 {
+            for bx in 0..3 {
+                assert_eq!(
+                    field.vectors[by * field.blocks_x + bx],
+                    (3.0, 1.0),
+                    "block {}, {}",
+                    bx,
+                    by
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mean_abs_diff_averages_and_rejects_mismatched_sizes() {
+        assert_eq!(mean_abs_diff(&[1, 2, 3], &[1, 2, 3]), 0.0);
+        assert_eq!(mean_abs_diff(&[0, 10], &[10, 0]), 10.0);
+        assert_eq!(mean_abs_diff(&[0, 10], &[10]), f32::MAX);
+    }
+}
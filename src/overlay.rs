@@ -0,0 +1,36 @@
+use crate::postprocess;
+
+// Mask overlay colors, RGBA drawn over the camera frame
+const PERSON_TINT: [u8; 4] = [40, 120, 255, 110]; // Scaled by the person alpha
+const SOFT_TINT: [u8; 4] = [255, 0, 200, 200]; // Scaled by the uncertainty, strongest at alpha 0.5
+const CONTOUR_COLOR: [u8; 4] = [255, 230, 0, 255];
+const SOFT_RANGE: (u8, u8) = (16, 240); // Alpha strictly between these is soft
+
+// RGBA overlay of a person alpha (255 = person) for the mask overlay view: the person tinted, the
+// soft (unknown) alpha highlighted, and a contour `contour_width` pixels wide along the inside of
+// the alpha 0.5 line. Borders of the frame are not contoured.
+pub fn mask_overlay(alpha: &[u8], width: usize, height: usize, contour_width: u32) -> Vec<u8> {
+    let inside: Vec<u8> = alpha.iter().map(|&a| if a >= 128 { 255 } else { 0 }).collect();
+    let core = postprocess::erode(&inside, width, height, 2 * contour_width + 1);
+
+    alpha
+        .iter()
+        .zip(inside.iter().zip(&core))
+        .flat_map(|(&a, (&inside, &core))| {
+            if inside != core {
+                CONTOUR_COLOR
+            } else if a > SOFT_RANGE.0 && a < SOFT_RANGE.1 {
+                let uncertainty = 255 - (2 * a as i32 - 255).unsigned_abs();
+                tint(SOFT_TINT, uncertainty.max(64))
+            } else {
+                tint(PERSON_TINT, a as u32)
+            }
+        })
+        .collect()
+}
+
+// Color with its opacity scaled by `weight` (0-255)
+fn tint(color: [u8; 4], weight: u32) -> [u8; 4] {
+    let [r, g, b, a] = color;
+    [r, g, b, (a as u32 * weight / 255) as u8]
+}
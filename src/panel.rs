@@ -0,0 +1,270 @@
+use raylib::prelude::*;
+
+use crate::SETUP;
+use crate::bgremoval::{InferenceCadence, Settings};
+use crate::capture::CameraControls;
+use crate::viewer::{BackgroundImage, BackgroundMode, ViewLayout, ViewOptions};
+
+pub const PANEL_WIDTH: f32 = 380.0;
+const ROW_HEIGHT: f32 = 30.0;
+const MARGIN: f32 = 12.0;
+const LABEL_WIDTH: f32 = 120.0; // Slider labels, left of the controls
+const VALUE_WIDTH: f32 = 56.0; // Slider values, right of the controls
+
+#[derive(Clone, Copy, PartialEq)]
+enum Dropdown {
+    Layout,
+    Background,
+    Cadence,
+}
+
+// Buttons pressed on the panel this frame
+#[derive(Default)]
+pub struct PanelActions {
+    pub save: bool,
+    pub reset: bool,
+}
+
+// Immediate-mode (raygui) settings panel along the right edge of the window. Changes are written
+// straight into the settings and options it is drawn with; the viewer forwards them.
+#[derive(Default)]
+pub struct SettingsPanel {
+    pub open: bool,
+    dropdown: Option<Dropdown>, // Open dropdown, which takes the input until it closes
+}
+
+impl SettingsPanel {
+    pub fn bounds(width: f32, height: f32) -> Rectangle {
+        Rectangle::new(width - PANEL_WIDTH, 0.0, PANEL_WIDTH, height)
+    }
+
+    pub fn draw(
+        &mut self,
+        d: &mut RaylibDrawHandle,
+        bounds: Rectangle,
+        settings: &mut Settings,
+        options: &mut ViewOptions,
+        images: &[BackgroundImage],
+        camera: (&str, &mut CameraControls),
+    ) -> PanelActions {
+        let mut rows = Rows {
+            bounds,
+            y: bounds.y + ROW_HEIGHT + MARGIN,
+        };
+        d.gui_panel(bounds, "Settings");
+        // An open dropdown list covers the rows below it, which must not react meanwhile
+        if self.dropdown.is_some() {
+            d.gui_lock();
+        }
+
+        rows.header(d, "View");
+        let layout_rect = rows.control(d, "Layout");
+
+        rows.header(d, "Segmentation");
+        let (min, max) = settings.threshold_range();
+        let mut threshold = settings.threshold();
+        rows.slider(d, "Threshold", &mut threshold, min, max);
+        if threshold != settings.threshold() {
+            settings.set_threshold(threshold);
+        }
+        let mut feather = settings.feather as f32;
+        rows.slider(d, "Feather", &mut feather, 0.0, 8.0);
+        settings.feather = feather.round() as u32;
+        rows.slider(d, "Smoothing", &mut settings.temporal_smoothing, 0.0, 0.95);
+
+        rows.header(d, "Background");
+        let background_rect = rows.control(d, "Mode");
+        let mut color = options.background_color.map(|c| c as f32);
+        for (label, channel) in ["Red", "Green", "Blue"].iter().zip(color.iter_mut()) {
+            rows.slider(d, label, channel, 0.0, 255.0);
+        }
+        options.background_color = color.map(|c| c.round() as u8);
+        if !images.is_empty() {
+            let names: Vec<&str> = images.iter().map(|image| image.name.as_str()).collect();
+            let mut image = options.background_image as i32;
+            d.gui_combo_box(rows.control(d, "Image"), &names.join(";"), &mut image);
+            options.background_image = image as usize;
+        }
+
+        rows.header(d, "Inference");
+        let cadence_rect = rows.control(d, "Cadence");
+        match &mut settings.inference_cadence {
+            InferenceCadence::EveryFrame => {}
+            InferenceCadence::EveryNth(n) => {
+                let mut value = *n as f32;
+                rows.slider(d, "Every Nth", &mut value, 1.0, 10.0);
+                *n = value.round() as u32;
+            }
+            InferenceCadence::TargetFps(fps) => rows.slider(d, "Target FPS", fps, 1.0, 60.0),
+            InferenceCadence::Adaptive { motion_threshold, .. } => {
+                rows.slider(d, "Motion", motion_threshold, 0.0, 10.0)
+            }
+        }
+
+        let (label, camera) = camera;
+        rows.header(d, &format!("Camera: {}", label));
+        if camera.controls.is_empty() {
+            rows.note(d, "No adjustable controls");
+        }
+        let buttons_y = bounds.y + bounds.height - MARGIN - ROW_HEIGHT;
+        for i in 0..camera.controls.len() {
+            if rows.y + ROW_HEIGHT > buttons_y {
+                break;
+            }
+            let control = &camera.controls[i];
+            let mut value = control.value as f32;
+            let name = control.name.chars().take(14).collect::<String>();
+            rows.slider(d, &name, &mut value, control.minimum as f32, control.maximum as f32);
+            if value.round() as i64 != control.value {
+                camera.set(i, value.round() as i64);
+            }
+        }
+
+        let button_width = (bounds.width - 3.0 * MARGIN) / 2.0;
+        let actions = PanelActions {
+            save: d.gui_button(
+                Rectangle::new(bounds.x + MARGIN, buttons_y, button_width, ROW_HEIGHT),
+                "Save to config",
+            ),
+            reset: d.gui_button(
+                Rectangle::new(
+                    bounds.x + 2.0 * MARGIN + button_width,
+                    buttons_y,
+                    button_width,
+                    ROW_HEIGHT,
+                ),
+                "Reset to defaults",
+            ),
+        };
+        d.gui_unlock();
+
+        // Dropdowns last, so that their lists draw over the rows below
+        let mut cadence = match settings.inference_cadence {
+            InferenceCadence::EveryFrame => 0,
+            InferenceCadence::EveryNth(_) => 1,
+            InferenceCadence::TargetFps(_) => 2,
+            InferenceCadence::Adaptive { .. } => 3,
+        };
+        let before = cadence;
+        if d.gui_dropdown_box(
+            cadence_rect,
+            "Every frame;Every Nth;Target FPS;Adaptive",
+            &mut cadence,
+            self.dropdown == Some(Dropdown::Cadence),
+        ) {
+            self.toggle(Dropdown::Cadence);
+        }
+        if cadence != before {
+            settings.inference_cadence = match cadence {
+                0 => InferenceCadence::EveryFrame,
+                1 => InferenceCadence::EveryNth(2),
+                2 => InferenceCadence::TargetFps(15.0),
+                _ => match SETUP.inference_cadence {
+                    adaptive @ InferenceCadence::Adaptive { .. } => adaptive,
+                    _ => InferenceCadence::Adaptive {
+                        motion_threshold: 2.0,
+                        max_skip: 4,
+                    },
+                },
+            };
+        }
+
+        let mut background = BackgroundMode::ALL
+            .iter()
+            .position(|&mode| mode == options.background)
+            .unwrap_or(0) as i32;
+        if d.gui_dropdown_box(
+            background_rect,
+            "Color;Blur;Image",
+            &mut background,
+            self.dropdown == Some(Dropdown::Background),
+        ) {
+            self.toggle(Dropdown::Background);
+        }
+        options.background = BackgroundMode::ALL[background.clamp(0, 2) as usize];
+
+        let mut layout = ViewLayout::ALL
+            .iter()
+            .position(|&layout| layout == options.layout)
+            .unwrap_or(0) as i32;
+        if d.gui_dropdown_box(
+            layout_rect,
+            "Composite;Side by side;Debug grid;Mask overlay",
+            &mut layout,
+            self.dropdown == Some(Dropdown::Layout),
+        ) {
+            self.toggle(Dropdown::Layout);
+        }
+        options.layout = ViewLayout::ALL[layout.clamp(0, 3) as usize];
+
+        actions
+    }
+
+    fn toggle(&mut self, dropdown: Dropdown) {
+        self.dropdown = match self.dropdown {
+            Some(open) if open == dropdown => None,
+            _ => Some(dropdown),
+        };
+    }
+}
+
+// Vertical layout of the panel rows
+struct Rows {
+    bounds: Rectangle,
+    y: f32, // Top of the next row
+}
+
+impl Rows {
+    // Control area of the next row, with its label on the left
+    fn control(&mut self, d: &mut RaylibDrawHandle, label: &str) -> Rectangle {
+        d.gui_label(
+            Rectangle::new(self.bounds.x + MARGIN, self.y, LABEL_WIDTH, ROW_HEIGHT - 6.0),
+            label,
+        );
+        let rect = Rectangle::new(
+            self.bounds.x + MARGIN + LABEL_WIDTH,
+            self.y,
+            self.bounds.width - 2.0 * MARGIN - LABEL_WIDTH - VALUE_WIDTH,
+            ROW_HEIGHT - 6.0,
+        );
+        self.y += ROW_HEIGHT;
+        rect
+    }
+
+    fn slider(&mut self, d: &mut RaylibDrawHandle, label: &str, value: &mut f32, min: f32, max: f32) {
+        let rect = self.control(d, label);
+        let text = if max - min > 20.0 {
+            format!("{:.0}", value)
+        } else {
+            format!("{:.2}", value)
+        };
+        d.gui_slider_bar(rect, "", &text, value, min, max);
+    }
+
+    fn note(&mut self, d: &mut RaylibDrawHandle, text: &str) {
+        d.gui_label(
+            Rectangle::new(
+                self.bounds.x + MARGIN,
+                self.y,
+                self.bounds.width - 2.0 * MARGIN,
+                ROW_HEIGHT - 6.0,
+            ),
+            text,
+        );
+        self.y += ROW_HEIGHT;
+    }
+
+    fn header(&mut self, d: &mut RaylibDrawHandle, text: &str) {
+        self.y += MARGIN / 2.0;
+        d.gui_line(
+            Rectangle::new(
+                self.bounds.x + MARGIN,
+                self.y,
+                self.bounds.width - 2.0 * MARGIN,
+                ROW_HEIGHT - 6.0,
+            ),
+            text,
+        );
+        self.y += ROW_HEIGHT;
+    }
+}
@@ -0,0 +1,236 @@
+use std::sync::mpsc::{Receiver, Sender};
+
+use anyhow::Result;
+
+use crate::bgremoval::{self, Control, MlFrames, Segmentation, Settings};
+use crate::postprocess::{self, MaskOp, TemporalSmoothing};
+use crate::viewer::{Frame, RaylibFrames};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlateFusion {
+    PlateOnly,    // The plate difference alone, no model is loaded
+    Union,        // Person in either mask: fills parts of the person that match the plate colors
+    Intersection, // Person in both masks: drops shadows and lighting changes the model rejects
+    Average,      // Mean of both alphas
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlateSubtraction {
+    pub threshold: f32,              // Color difference (0-255) up to which a pixel is background
+    pub softness: f32,               // Difference range above the threshold over which alpha ramps up
+    pub lighting_compensation: bool, // Follow global exposure and white balance changes of the camera
+    pub capture_frames: u32,         // Frames averaged into the plate, against sensor noise
+    pub fusion: PlateFusion,
+}
+
+// Clean background plate of one camera, at the low resolution
+struct BackgroundPlate {
+    plate: Option<Vec<f32>>,          // RGB
+    capture: Option<(Vec<u32>, u32)>, // Channel sums and frames of a capture in progress
+}
+
+impl BackgroundPlate {
+    fn accumulate(&mut self, low_res_frame: &Frame, capture_frames: u32) {
+        let Some((sums, frames)) = self.capture.as_mut() else {
+            return;
+        };
+        let rgb = low_res_frame.as_rgb();
+        if sums.len() != rgb.len() {
+            *sums = vec![0; rgb.len()];
+        }
+        for (sum, &value) in sums.iter_mut().zip(&rgb) {
+            *sum += value as u32;
+        }
+        *frames += 1;
+
+        if *frames >= capture_frames.max(1) {
+            let frames = *frames as f32;
+            self.plate = Some(sums.iter().map(|&sum| sum as f32 / frames).collect());
+            self.capture = None;
+        }
+    }
+
+    // Person alpha (255 = person) by difference against the plate, None without a plate
+    fn alpha(&self, low_res_frame: &Frame, plate_subtraction: &PlateSubtraction) -> Option<Vec<u8>> {
+        let plate = self.plate.as_ref()?;
+        let rgb = low_res_frame.as_rgb();
+        if rgb.len() != plate.len() {
+            return None;
+        }
+        let gain = if plate_subtraction.lighting_compensation {
+            channel_gains(&rgb, plate)
+        } else {
+            [1.0; 3]
+        };
+
+        Some(
+            rgb.chunks_exact(3)
+                .zip(plate.chunks_exact(3))
+                .map(|(px, background)| {
+                    let difference = (0..3)
+                        .map(|c| (px[c] as f32 - background[c] * gain[c]).abs())
+                        .fold(0.0, f32::max);
+                    let alpha = if plate_subtraction.softness > 0.0 {
+                        ((difference - plate_subtraction.threshold) / plate_subtraction.softness).clamp(0.0, 1.0)
+                    } else if difference > plate_subtraction.threshold {
+                        1.0
+                    } else {
+                        0.0
+                    };
+                    (alpha * 255.0).round() as u8
+                })
+                .collect(),
+        )
+    }
+}
+
+// Per channel gain from the plate to the frame: the median ratio over a pixel grid, which the
+// person does not move as long as the background covers most of the frame
+fn channel_gains(rgb: &[u8], plate: &[f32]) -> [f32; 3] {
+    const STEP: usize = 7;
+    [0, 1, 2].map(|c| {
+        let mut ratios: Vec<f32> = (0..rgb.len() / 3)
+            .step_by(STEP)
+            .map(|i| (rgb[i * 3 + c] as f32 + 8.0) / (plate[i * 3 + c] + 8.0))
+            .collect();
+        ratios.sort_unstable_by(f32::total_cmp);
+        ratios.get(ratios.len() / 2).copied().unwrap_or(1.0).clamp(0.5, 2.0)
+    })
+}
+
+// Background plates of every source, inactive unless the segmentation is plate subtraction
+pub struct Plates {
+    plate_subtraction: Option<PlateSubtraction>,
+    mask_ops: Vec<MaskOp>, // Post-processing of the plate difference
+    plates: Vec<BackgroundPlate>,
+}
+
+impl Plates {
+    pub fn new(source_count: usize, settings: &Settings) -> Self {
+        let plate_subtraction = match settings.segmentation {
+            Segmentation::PlateSubtraction(plate_subtraction) => Some(plate_subtraction),
+            _ => None,
+        };
+        Self {
+            plate_subtraction,
+            mask_ops: settings.mask_ops(),
+            plates: (0..source_count)
+                .map(|_| BackgroundPlate {
+                    plate: None,
+                    capture: None,
+                })
+                .collect(),
+        }
+    }
+
+    // Replaces the subtraction settings, keeping the captured plates
+    pub fn configure(&mut self, settings: &Settings) {
+        if let (Some(_), Segmentation::PlateSubtraction(plate_subtraction)) =
+            (self.plate_subtraction, settings.segmentation)
+        {
+            self.plate_subtraction = Some(plate_subtraction);
+        }
+        self.mask_ops = settings.mask_ops();
+    }
+
+    // Starts capturing the plate of a source from its next frames, which should show nobody
+    pub fn capture(&mut self, source: usize) {
+        let Some(plate_subtraction) = self.plate_subtraction else {
+            eprintln!("Plate capture requested without plate subtraction, ignored");
+            return;
+        };
+        if let Some(plate) = self.plates.get_mut(source) {
+            println!(
+                "Capturing the background plate of source {} over {} frames",
+                source, plate_subtraction.capture_frames
+            );
+            plate.capture = Some((Vec::new(), 0));
+        }
+    }
+
+    // Fuses the low resolution model alpha of a frame with its plate difference. Without a plate
+    // yet the model alpha is kept, which in `PlateOnly` mode shows the whole frame.
+    pub fn apply(&mut self, source: usize, low_res_frame: &Frame, alpha: Vec<u8>) -> Vec<u8> {
+        let (Some(plate_subtraction), Some(plate)) = (self.plate_subtraction, self.plates.get_mut(source)) else {
+            return alpha;
+        };
+        plate.accumulate(low_res_frame, plate_subtraction.capture_frames);
+        let Some(plate_alpha) = plate.alpha(low_res_frame, &plate_subtraction) else {
+            return alpha;
+        };
+
+        let plate_alpha = postprocess::apply(
+            &plate_alpha,
+            low_res_frame.width as usize,
+            low_res_frame.height as usize,
+            &self.mask_ops,
+        );
+        match plate_subtraction.fusion {
+            PlateFusion::PlateOnly => plate_alpha,
+            PlateFusion::Union => plate_alpha.iter().zip(&alpha).map(|(&p, &m)| p.max(m)).collect(),
+            PlateFusion::Intersection => plate_alpha.iter().zip(&alpha).map(|(&p, &m)| p.min(m)).collect(),
+            PlateFusion::Average => plate_alpha
+                .iter()
+                .zip(&alpha)
+                .map(|(&p, &m)| (p as u16 + m as u16).div_ceil(2) as u8)
+                .collect(),
+        }
+    }
+}
+
+// Plate subtraction without a model: every frame is segmented against its camera's plate and
+// composited like a model mask. Until a plate is captured the whole frame is shown.
+pub fn run_plate_subtraction(
+    mut settings: Settings,
+    ml_rx: Receiver<MlFrames>,
+    outputs: Vec<Sender<RaylibFrames>>,
+    controls: Receiver<Control>,
+) -> Result<()> {
+    let mut plates = Plates::new(outputs.len(), &settings);
+    let mut smoothing = TemporalSmoothing::new(outputs.len());
+    println!("Plate subtraction mode, press B with nobody in frame to capture the background plate");
+
+    while let Ok(MlFrames {
+        source,
+        high_res_frame,
+        low_res_frame,
+        instant,
+    }) = ml_rx.recv()
+    {
+        for control in controls.try_iter() {
+            match control {
+                Control::CapturePlate(source) => plates.capture(source),
+                Control::Settings(new_settings) => {
+                    settings = new_settings;
+                    plates.configure(&settings);
+                }
+                Control::LoadModel(path) => eprintln!("Plate subtraction mode, model {} not loaded", path.display()),
+            }
+        }
+        let Some(output) = outputs.get(source) else {
+            return Err(anyhow::anyhow!(
+                "Frame from source {} but only {} outputs",
+                source,
+                outputs.len()
+            ));
+        };
+
+        let everything = vec![255; (low_res_frame.width * low_res_frame.height) as usize];
+        let alpha = plates.apply(source, &low_res_frame, everything);
+        let alpha = smoothing.apply(source, alpha, settings.temporal_smoothing);
+        if output
+            .send(bgremoval::compose(
+                high_res_frame,
+                low_res_frame,
+                &alpha,
+                instant,
+                instant,
+                &settings,
+            )?)
+            .is_err()
+        {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+
+use crate::refine::box_filter;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaskOp {
+    Erode(u32),         // Square kernel size in mask pixels
+    Dilate(u32),        // Square kernel size in mask pixels
+    Open(u32),          // Erode then dilate: removes speckles smaller than the kernel
+    Close(u32),         // Dilate then erode: closes gaps smaller than the kernel
+    FillHoles,          // Fills background regions not connected to the mask border
+    KeepLargest(usize), // Keeps the N largest person components
+    Feather(u32),       // Softens the mask edge with the given radius
+}
+
+// Runs the post-processing chain on a person alpha (255 = person), in order.
+pub fn apply(alpha: &[u8], width: usize, height: usize, ops: &[MaskOp]) -> Vec<u8> {
+    let mut alpha = alpha.to_vec();
+    for op in ops {
+        alpha = match *op {
+            MaskOp::Erode(size) => erode(&alpha, width, height, size),
+            MaskOp::Dilate(size) => dilate(&alpha, width, height, size),
+            MaskOp::Open(size) => dilate(&erode(&alpha, width, height, size), width, height, size),
+            MaskOp::Close(size) => erode(&dilate(&alpha, width, height, size), width, height, size),
+            MaskOp::FillHoles => fill_holes(&alpha, width, height),
+            MaskOp::KeepLargest(count) => keep_largest(&alpha, width, height, count),
+            MaskOp::Feather(radius) => feather(&alpha, width, height, radius),
+        };
+    }
+    alpha
+}
+
+// Exponential moving average of the masks of every source, against flicker between inferences
+pub struct TemporalSmoothing {
+    previous: Vec<Vec<f32>>,
+}
+
+impl TemporalSmoothing {
+    pub fn new(source_count: usize) -> Self {
+        Self {
+            previous: vec![Vec::new(); source_count],
+        }
+    }
+
+    // Blends the alpha of a source with its previous result, `smoothing` (0-1) being the weight of
+    // the previous one. Starts over when the mask size changes.
+    pub fn apply(&mut self, source: usize, alpha: Vec<u8>, smoothing: f32) -> Vec<u8> {
+        let Some(previous) = self.previous.get_mut(source) else {
+            return alpha;
+        };
+        if smoothing <= 0.0 || previous.len() != alpha.len() {
+            *previous = alpha.iter().map(|&a| a as f32).collect();
+            return alpha;
+        }
+        let smoothing = smoothing.min(1.0);
+        for (previous, &a) in previous.iter_mut().zip(&alpha) {
+            *previous = *previous * smoothing + a as f32 * (1.0 - smoothing);
+        }
+        previous.iter().map(|&a| a.round() as u8).collect()
+    }
+}
+
+pub fn erode(alpha: &[u8], width: usize, height: usize, size: u32) -> Vec<u8> {
+    rank_filter(alpha, width, height, size, u8::min)
+}
+
+pub fn dilate(alpha: &[u8], width: usize, height: usize, size: u32) -> Vec<u8> {
+    rank_filter(alpha, width, height, size, u8::max)
+}
+
+// Separable min/max filter over a square kernel, clamped at the borders
+fn rank_filter(alpha: &[u8], width: usize, height: usize, size: u32, pick: fn(u8, u8) -> u8) -> Vec<u8> {
+    let radius = (size / 2) as usize;
+    if radius == 0 {
+        return alpha.to_vec();
+    }
+
+    let mut tmp = vec![0u8; width * height];
+    for y in 0..height {
+        let row = &alpha[y * width..(y + 1) * width];
+        for x in 0..width {
+            let window = &row[x.saturating_sub(radius)..(x + radius + 1).min(width)];
+            tmp[y * width + x] = window.iter().copied().reduce(pick).unwrap_or(0);
+        }
+    }
+
+    let mut out = vec![0u8; width * height];
+    for x in 0..width {
+        for y in 0..height {
+            out[y * width + x] = (y.saturating_sub(radius)..(y + radius + 1).min(height))
+                .map(|yy| tmp[yy * width + x])
+                .reduce(pick)
+                .unwrap_or(0);
+        }
+    }
+    out
+}
+
+pub fn fill_holes(alpha: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut outside = vec![false; width * height];
+    let mut queue = VecDeque::new();
+
+    for x in 0..width {
+        queue.push_back((x, 0));
+        queue.push_back((x, height - 1));
+    }
+    for y in 0..height {
+        queue.push_back((0, y));
+        queue.push_back((width - 1, y));
+    }
+
+    while let Some((x, y)) = queue.pop_front() {
+        let i = y * width + x;
+        if outside[i] || alpha[i] >= 128 {
+            continue;
+        }
+        outside[i] = true;
+        for (nx, ny) in neighbours(x, y, width, height) {
+            queue.push_back((nx, ny));
+        }
+    }
+
+    alpha
+        .iter()
+        .zip(outside.iter())
+        .map(|(&a, &outside)| if outside { a } else { 255 })
+        .collect()
+}
+
+pub fn keep_largest(alpha: &[u8], width: usize, height: usize, count: usize) -> Vec<u8> {
+    let (labels, sizes) = label_components(alpha, width, height);
+
+    let mut ranked: Vec<usize> = (0..sizes.len()).collect();
+    ranked.sort_by(|a, b| sizes[*b].cmp(&sizes[*a]));
+    let mut keep = vec![false; sizes.len()];
+    for &label in ranked.iter().take(count) {
+        keep[label] = true;
+    }
+
+    alpha
+        .iter()
+        .zip(labels.iter())
+        .map(|(&a, &label)| match label {
+            Some(label) if keep[label] => a,
+            _ => 0,
+        })
+        .collect()
+}
+
+// 4-connected components of person pixels, returns per pixel labels and per label sizes
+pub fn label_components(alpha: &[u8], width: usize, height: usize) -> (Vec<Option<usize>>, Vec<usize>) {
+    let mut labels = vec![None; width * height];
+    let mut sizes = Vec::new();
+    let mut queue = VecDeque::new();
+
+    for start in 0..width * height {
+        if alpha[start] < 128 || labels[start].is_some() {
+            continue;
+        }
+        let label = sizes.len();
+        let mut size = 0;
+        labels[start] = Some(label);
+        queue.push_back((start % width, start / width));
+
+        while let Some((x, y)) = queue.pop_front() {
+            size += 1;
+            for (nx, ny) in neighbours(x, y, width, height) {
+                let i = ny * width + nx;
+                if alpha[i] >= 128 && labels[i].is_none() {
+                    labels[i] = Some(label);
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+        sizes.push(size);
+    }
+    (labels, sizes)
+}
+
+pub fn feather(alpha: &[u8], width: usize, height: usize, radius: u32) -> Vec<u8> {
+    if radius == 0 {
+        return alpha.to_vec();
+    }
+    let plane: Vec<f32> = alpha.iter().map(|&a| a as f32).collect();
+    // Two box passes approximate a gaussian falloff
+    let plane = box_filter(&plane, width, height, radius as usize);
+    let plane = box_filter(&plane, width, height, radius as usize);
+    plane.iter().map(|&a| a.round().clamp(0.0, 255.0) as u8).collect()
+}
+
+fn neighbours(x: usize, y: usize, width: usize, height: usize) -> impl Iterator<Item = (usize, usize)> {
+    [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)]
+        .into_iter()
+        .filter(move |&(nx, ny)| nx < width && ny < height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Alpha from rows of `#` (person) and `.` (background)
+    fn mask(rows: &[&str]) -> (Vec<u8>, usize, usize) {
+        let alpha = rows
+            .iter()
+            .flat_map(|row| row.chars().map(|c| if c == '#' { 255 } else { 0 }))
+            .collect();
+        (alpha, rows[0].len(), rows.len())
+    }
+
+    #[test]
+    fn fill_holes_fills_enclosed_background() {
+        let (alpha, width, height) = mask(&["......", ".####.", ".#..#.", ".####.", "......"]);
+        let (expected, _, _) = mask(&["......", ".####.", ".####.", ".####.", "......"]);
+        assert_eq!(fill_holes(&alpha, width, height), expected);
+    }
+
+    #[test]
+    fn fill_holes_keeps_background_open_to_the_border() {
+        let (alpha, width, height) = mask(&[".####.", ".#..#.", ".#....", ".####."]);
+        assert_eq!(fill_holes(&alpha, width, height), alpha);
+    }
+
+    #[test]
+    fn keep_largest_drops_smaller_components() {
+        let (alpha, width, height) = mask(&["##...#", "##....", "......", "...##."]);
+        let (expected, _, _) = mask(&["##....", "##....", "......", "......"]);
+        assert_eq!(keep_largest(&alpha, width, height, 1), expected);
+
+        let (expected, _, _) = mask(&["##....", "##....", "......", "...##."]);
+        assert_eq!(keep_largest(&alpha, width, height, 2), expected);
+    }
+
+    #[test]
+    fn erode_and_dilate_shrink_and_grow_by_the_kernel_radius() {
+        let (alpha, width, height) = mask(&[".....", ".###.", ".###.", ".###.", "....."]);
+        let (eroded, _, _) = mask(&[".....", ".....", "..#..", ".....", "....."]);
+        let (dilated, _, _) = mask(&["#####", "#####", "#####", "#####", "#####"]);
+        assert_eq!(erode(&alpha, width, height, 3), eroded);
+        assert_eq!(dilate(&alpha, width, height, 3), dilated);
+        assert_eq!(dilate(&eroded, width, height, 3), alpha);
+    }
+}
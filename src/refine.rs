@@ -0,0 +1,172 @@
+use anyhow::Result;
+use fast_image_resize::images::{TypedImage, TypedImageRef};
+use fast_image_resize::pixels::F32;
+use fast_image_resize::{FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer, SrcCropping};
+
+use crate::chroma::ChromaKey;
+use crate::viewer::Frame;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaskRefinement {
+    // Plain upsampling of the model mask (Box filter)
+    Upsample,
+    // Fast guided filter using the full resolution frame as guidance
+    GuidedFilter {
+        radius: u32,    // Window radius in full resolution pixels
+        eps: f32,       // Regularization, higher values give smoother edges
+        subsample: u32, // Working resolution divider for the filter coefficients
+    },
+    // Trimap around the mask edge with the unknown band solved by sampling matting at full
+    // resolution, or keyed against a backdrop (hybrid ML + chroma key), see `matting::matte`
+    Matting {
+        band: u32,                     // Unknown band half width in full resolution pixels
+        chroma_key: Option<ChromaKey>, // Key the band against a green/blue screen instead
+    },
+}
+
+// Upsamples a low resolution alpha (0 = background, 255 = person) to the size of `guide`,
+// snapping the mask edges to the edges of the full resolution frame.
+pub fn guided_upsample(
+    guide: &Frame,
+    alpha: &[u8],
+    alpha_width: u32,
+    alpha_height: u32,
+    radius: u32,
+    eps: f32,
+    subsample: u32,
+) -> Result<Vec<u8>> {
+    let full_width = guide.width as u32;
+    let full_height = guide.height as u32;
+    let subsample = subsample.max(1);
+    let work_width = (full_width / subsample).max(1);
+    let work_height = (full_height / subsample).max(1);
+    let work_radius = (radius / subsample).max(1) as usize;
+
+    let mut resizer = Resizer::new();
+
+    let guide_full = luma(guide);
+    let guide_low = resize_plane(
+        &mut resizer,
+        &guide_full,
+        full_width,
+        full_height,
+        work_width,
+        work_height,
+    )?;
+
+    let alpha: Vec<f32> = alpha.iter().map(|&a| a as f32 / 255.0).collect();
+    let alpha_low = resize_plane(&mut resizer, &alpha, alpha_width, alpha_height, work_width, work_height)?;
+
+    let (w, h) = (work_width as usize, work_height as usize);
+    let mean_i = box_filter(&guide_low, w, h, work_radius);
+    let mean_p = box_filter(&alpha_low, w, h, work_radius);
+    let corr_ii = box_filter(&mul(&guide_low, &guide_low), w, h, work_radius);
+    let corr_ip = box_filter(&mul(&guide_low, &alpha_low), w, h, work_radius);
+
+    let a: Vec<f32> = (0..w * h)
+        .map(|i| {
+            let var_i = corr_ii[i] - mean_i[i] * mean_i[i];
+            let cov_ip = corr_ip[i] - mean_i[i] * mean_p[i];
+            cov_ip / (var_i + eps)
+        })
+        .collect();
+    let b: Vec<f32> = (0..w * h).map(|i| mean_p[i] - a[i] * mean_i[i]).collect();
+
+    let mean_a = box_filter(&a, w, h, work_radius);
+    let mean_b = box_filter(&b, w, h, work_radius);
+    let mean_a = resize_plane(&mut resizer, &mean_a, work_width, work_height, full_width, full_height)?;
+    let mean_b = resize_plane(&mut resizer, &mean_b, work_width, work_height, full_width, full_height)?;
+
+    Ok(guide_full
+        .iter()
+        .zip(mean_a.iter().zip(mean_b.iter()))
+        .map(|(&i, (&a, &b))| ((a * i + b).clamp(0.0, 1.0) * 255.0).round() as u8)
+        .collect())
+}
+
+fn luma(frame: &Frame) -> Vec<f32> {
+    let channels = match frame.pixel_type {
+        PixelType::U8x3 => 3,
+        PixelType::U8x4 => 4,
+        _ => panic!("Unsupported pixel type: {:?}", frame.pixel_type),
+    };
+    frame
+        .data
+        .chunks_exact(channels)
+        .map(|px| (0.299 * px[0] as f32 + 0.587 * px[1] as f32 + 0.114 * px[2] as f32) / 255.0)
+        .collect()
+}
+
+fn mul(a: &[f32], b: &[f32]) -> Vec<f32> {
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).collect()
+}
+
+pub fn resize_plane(
+    resizer: &mut Resizer,
+    src: &[f32],
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+) -> Result<Vec<f32>> {
+    let src_pixels: Vec<F32> = src.iter().map(|&v| F32::new(v)).collect();
+    let src_img = TypedImageRef::new(src_width, src_height, &src_pixels)?;
+    let mut dst_img = TypedImage::<F32>::new(dst_width, dst_height);
+
+    let options = ResizeOptions {
+        algorithm: ResizeAlg::Convolution(FilterType::Bilinear),
+        cropping: SrcCropping::None,
+        mul_div_alpha: false,
+    };
+
+    resizer.resize_typed(&src_img, &mut dst_img, &options)?;
+
+    Ok(dst_img.pixels().iter().map(|px| px.0).collect())
+}
+
+// Mean over a (2 * radius + 1)^2 window, clamped at the borders
+pub fn box_filter(src: &[f32], width: usize, height: usize, radius: usize) -> Vec<f32> {
+    let mut tmp = vec![0f32; width * height];
+    for y in 0..height {
+        box_line(
+            &src[y * width..(y + 1) * width],
+            &mut tmp[y * width..(y + 1) * width],
+            radius,
+        );
+    }
+
+    let mut out = vec![0f32; width * height];
+    let mut column = vec![0f32; height];
+    let mut column_out = vec![0f32; height];
+    for x in 0..width {
+        for (y, v) in column.iter_mut().enumerate() {
+            *v = tmp[y * width + x];
+        }
+        box_line(&column, &mut column_out, radius);
+        for (y, v) in column_out.iter().enumerate() {
+            out[y * width + x] = *v;
+        }
+    }
+    out
+}
+
+fn box_line(src: &[f32], dst: &mut [f32], radius: usize) {
+    let len = src.len();
+    let mut sum = 0f32;
+    let mut count = 0usize;
+    for &v in &src[..radius.min(len)] {
+        sum += v;
+        count += 1;
+    }
+    for i in 0..len {
+        if i + radius < len {
+            sum += src[i + radius];
+            count += 1;
+        }
+        if i > radius {
+            sum -= src[i - radius - 1];
+            count -= 1;
+        }
+        dst[i] = sum / count as f32;
+    }
+}
@@ -0,0 +1,260 @@
+use anyhow::Result;
+use fast_image_resize::images::{Image, ImageRef};
+use fast_image_resize::{CropBox, FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer, SrcCropping};
+
+use crate::viewer::Frame;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RoiTracking {
+    pub margin: f32,           // Margin added around the person, as a fraction of the box size
+    pub min_area: f32,         // Smallest person area (fraction of the frame) still tracked
+    pub refresh_interval: u32, // Inferences on the crop before running on the full frame again
+}
+
+// Region of the full resolution frame, in pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Roi {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Roi {
+    pub fn full(width: u32, height: u32) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+
+    // Region scaled from a frame of `frame_width` x `frame_height` to one of `width` x `height`,
+    // as (x0, y0, x1, y1 exclusive)
+    pub fn scaled(&self, frame_width: u32, frame_height: u32, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let scale_x = width as f32 / frame_width as f32;
+        let scale_y = height as f32 / frame_height as f32;
+        (
+            (self.x as f32 * scale_x).round() as u32,
+            (self.y as f32 * scale_y).round() as u32,
+            (((self.x + self.width) as f32 * scale_x).round() as u32).min(width),
+            (((self.y + self.height) as f32 * scale_y).round() as u32).min(height),
+        )
+    }
+}
+
+// Keeps a region around the person of the previous mask, so the model sees the person
+// at a higher resolution. Falls back to the full frame when the person is lost.
+pub struct RoiTracker {
+    setup: RoiTracking,
+    roi: Option<Roi>,
+    inferences_since_full: u32,
+}
+
+impl RoiTracker {
+    pub fn new(setup: RoiTracking) -> Self {
+        Self {
+            setup,
+            roi: None,
+            inferences_since_full: 0,
+        }
+    }
+
+    // Region for the next inference, None for the full frame
+    pub fn roi(&self) -> Option<Roi> {
+        if self.inferences_since_full >= self.setup.refresh_interval {
+            return None;
+        }
+        self.roi
+    }
+
+    // Tracks the person of a full frame alpha (255 = person) for the next inference
+    pub fn update(&mut self, alpha: &[u8], alpha_width: u32, alpha_height: u32, frame_width: u32, frame_height: u32) {
+        if self.roi().is_none() {
+            self.inferences_since_full = 0;
+        } else {
+            self.inferences_since_full += 1;
+        }
+
+        self.roi = bounding_box(alpha, alpha_width, alpha_height, self.setup.min_area).and_then(|bbox| {
+            let scale_x = frame_width as f32 / alpha_width as f32;
+            let scale_y = frame_height as f32 / alpha_height as f32;
+            let (x0, y0, x1, y1) = bbox;
+            let width = (x1 - x0) as f32 * scale_x;
+            let height = (y1 - y0) as f32 * scale_y;
+            let center_x = (x0 + x1) as f32 * 0.5 * scale_x;
+            let center_y = (y0 + y1) as f32 * 0.5 * scale_y;
+
+            // Square crop so the person is not distorted at the model input
+            let side = width.max(height) * (1.0 + 2.0 * self.setup.margin);
+            let crop_width = side.min(frame_width as f32);
+            let crop_height = side.min(frame_height as f32);
+            if crop_width * crop_height > 0.9 * (frame_width * frame_height) as f32 {
+                return None; // Not worth cropping
+            }
+
+            let x = (center_x - crop_width * 0.5).clamp(0.0, frame_width as f32 - crop_width);
+            let y = (center_y - crop_height * 0.5).clamp(0.0, frame_height as f32 - crop_height);
+            Some(Roi {
+                x: x as u32,
+                y: y as u32,
+                width: crop_width as u32,
+                height: crop_height as u32,
+            })
+        });
+    }
+}
+
+// Bounding box (x0, y0, x1, y1 exclusive) of the person pixels, None if smaller than `min_area`
+pub fn bounding_box(alpha: &[u8], width: u32, height: u32, min_area: f32) -> Option<(u32, u32, u32, u32)> {
+    let (width, height) = (width as usize, height as usize);
+    let mut bbox = (width, height, 0, 0);
+    let mut count = 0usize;
+    for y in 0..height {
+        for (x, &a) in alpha[y * width..(y + 1) * width].iter().enumerate() {
+            if a >= 128 {
+                count += 1;
+                bbox = (bbox.0.min(x), bbox.1.min(y), bbox.2.max(x + 1), bbox.3.max(y + 1));
+            }
+        }
+    }
+
+    if count == 0 || (count as f32) < min_area * (width * height) as f32 {
+        return None;
+    }
+    Some((bbox.0 as u32, bbox.1 as u32, bbox.2 as u32, bbox.3 as u32))
+}
+
+// Crops the region from the frame and resizes it to the model input size
+pub fn crop_frame(frame: &Frame, roi: Roi, width: u32, height: u32) -> Result<Frame> {
+    let src_img = ImageRef::new(frame.width as u32, frame.height as u32, &frame.data, frame.pixel_type)?;
+    let mut dst_img = Image::new(width, height, frame.pixel_type);
+
+    let options = ResizeOptions {
+        algorithm: ResizeAlg::Convolution(FilterType::Gaussian),
+        cropping: SrcCropping::Crop(CropBox {
+            left: roi.x as f64,
+            top: roi.y as f64,
+            width: roi.width as f64,
+            height: roi.height as f64,
+        }),
+        mul_div_alpha: false,
+    };
+
+    Resizer::new().resize(&src_img, &mut dst_img, &options)?;
+
+    Ok(Frame {
+        width: width as i32,
+        height: height as i32,
+        pixel_type: frame.pixel_type,
+        data: dst_img.into_vec(),
+    })
+}
+
+// Places the alpha computed on a crop into the (x0, y0, x1, y1) region of a full frame
+// alpha of `width` x `height`, everything outside the region is background
+pub fn paste_alpha(
+    crop_alpha: &[u8],
+    crop_width: u32,
+    crop_height: u32,
+    (x0, y0, x1, y1): (u32, u32, u32, u32),
+    width: u32,
+    height: u32,
+) -> Result<Vec<u8>> {
+    let mut alpha = vec![0u8; (width * height) as usize];
+    if x1 <= x0 || y1 <= y0 {
+        return Ok(alpha);
+    }
+
+    let src_img = ImageRef::new(crop_width, crop_height, crop_alpha, PixelType::U8)?;
+    let mut dst_img = Image::new(x1 - x0, y1 - y0, PixelType::U8);
+    let options = ResizeOptions {
+        algorithm: ResizeAlg::Convolution(FilterType::Bilinear),
+        cropping: SrcCropping::None,
+        mul_div_alpha: false,
+    };
+    Resizer::new().resize(&src_img, &mut dst_img, &options)?;
+
+    let region_width = (x1 - x0) as usize;
+    for (row, src) in dst_img.buffer().chunks_exact(region_width).enumerate() {
+        let start = (y0 as usize + row) * width as usize + x0 as usize;
+        alpha[start..start + region_width].copy_from_slice(src);
+    }
+    Ok(alpha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACKING: RoiTracking = RoiTracking {
+        margin: 0.5,
+        min_area: 0.01,
+        refresh_interval: 2,
+    };
+
+    // 100x100 alpha with a person square of `side` at (x, y)
+    fn square(x: usize, y: usize, side: usize) -> Vec<u8> {
+        let mut alpha = vec![0; 100 * 100];
+        for row in y..y + side {
+            alpha[row * 100 + x..row * 100 + x + side].fill(255);
+        }
+        alpha
+    }
+
+    #[test]
+    fn bounding_box_encloses_the_person() {
+        assert_eq!(
+            bounding_box(&square(10, 20, 30), 100, 100, 0.01),
+            Some((10, 20, 40, 50))
+        );
+        assert_eq!(bounding_box(&square(10, 20, 5), 100, 100, 0.01), None, "Below min_area");
+        assert_eq!(bounding_box(&vec![0; 100 * 100], 100, 100, 0.0), None);
+    }
+
+    #[test]
+    fn tracker_crops_a_square_around_the_person() {
+        let mut tracker = RoiTracker::new(TRACKING);
+        assert_eq!(tracker.roi(), None, "Full frame until a person is seen");
+
+        // Alpha at 100x100 of a 1000x1000 frame: person box 100x200 centered at (400, 500)
+        let mut alpha = vec![0; 100 * 100];
+        for row in 40..60 {
+            alpha[row * 100 + 35..row * 100 + 45].fill(255);
+        }
+        tracker.update(&alpha, 100, 100, 1000, 1000);
+        assert_eq!(
+            tracker.roi(),
+            Some(Roi {
+                x: 200,
+                y: 300,
+                width: 400,
+                height: 400,
+            })
+        );
+    }
+
+    #[test]
+    fn tracker_returns_to_the_full_frame_every_refresh_interval() {
+        let mut tracker = RoiTracker::new(TRACKING);
+        let alpha = square(40, 40, 20);
+        let mut full_frames = Vec::new();
+        for _ in 0..6 {
+            full_frames.push(tracker.roi().is_none());
+            tracker.update(&alpha, 100, 100, 100, 100);
+        }
+        assert_eq!(full_frames, [true, false, false, true, false, false]);
+    }
+
+    #[test]
+    fn paste_alpha_fills_only_the_region() {
+        let alpha = paste_alpha(&[255; 16], 4, 4, (2, 1, 6, 3), 8, 4).unwrap();
+        for y in 0..4 {
+            for x in 0..8 {
+                let inside = (2..6).contains(&x) && (1..3).contains(&y);
+                assert_eq!(alpha[y * 8 + x], if inside { 255 } else { 0 }, "pixel {}, {}", x, y);
+            }
+        }
+    }
+}
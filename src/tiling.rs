@@ -0,0 +1,202 @@
+use std::path::Path;
+use std::time::Instant;
+
+use crate::SETUP;
+use crate::backend::{self, InferenceBackend};
+use crate::bgremoval;
+use crate::imageio;
+use crate::refine::resize_plane;
+use crate::roi::{self, Roi};
+use crate::viewer::Frame;
+use anyhow::Result;
+use fast_image_resize::Resizer;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Tiling {
+    pub tile_size: u32,   // Tile side in image pixels, each tile is resized to the model input
+    pub overlap: u32,     // Overlap between neighbouring tiles in image pixels, blended with a linear ramp
+    pub max_batch: usize, // Tiles per model run when the model has a dynamic batch dimension
+}
+
+// Runs the model on overlapping tiles of a full resolution image and blends the tile
+// probabilities into a single full resolution alpha matte (255 = person)
+pub fn tiled_alpha(backend: &mut dyn InferenceBackend, frame: &Frame, tiling: Tiling) -> Result<Vec<u8>> {
+    let (width, height) = (frame.width as u32, frame.height as u32);
+    let (model_width, model_height) = (SETUP.small_dec_width, SETUP.small_dec_height);
+    let tile_size = tiling.tile_size.min(width).min(height).max(1);
+    let overlap = tiling.overlap.min(tile_size / 2);
+
+    let tiles: Vec<Roi> = tile_positions(height, tile_size, overlap)
+        .into_iter()
+        .flat_map(|y| {
+            tile_positions(width, tile_size, overlap).into_iter().map(move |x| Roi {
+                x,
+                y,
+                width: tile_size,
+                height: tile_size,
+            })
+        })
+        .collect();
+
+    let batch_size = if backend.input_spec().batching {
+        tiling.max_batch.max(1)
+    } else {
+        1
+    };
+
+    let mut resizer = Resizer::new();
+    let mut sum = vec![0f32; (width * height) as usize];
+    let mut weight_sum = vec![0f32; (width * height) as usize];
+
+    for batch in tiles.chunks(batch_size) {
+        let inputs = batch
+            .iter()
+            .map(|&tile| roi::crop_frame(frame, tile, model_width, model_height))
+            .collect::<Result<Vec<_>>>()?;
+        let probabilities = backend.run(&inputs.iter().collect::<Vec<_>>())?;
+
+        for (tile, probability) in batch.iter().zip(probabilities.iter()) {
+            let probability = resize_plane(
+                &mut resizer,
+                probability,
+                model_width,
+                model_height,
+                tile.width,
+                tile.height,
+            )?;
+
+            for ty in 0..tile.height {
+                let wy = ramp(ty, tile.y, tile.height, height, overlap);
+                for tx in 0..tile.width {
+                    let weight = wy * ramp(tx, tile.x, tile.width, width, overlap);
+                    let i = ((tile.y + ty) * width + tile.x + tx) as usize;
+                    sum[i] += probability[(ty * tile.width + tx) as usize] * weight;
+                    weight_sum[i] += weight;
+                }
+            }
+        }
+    }
+
+    Ok(sum
+        .iter()
+        .zip(weight_sum.iter())
+        .map(|(&s, &w)| ((s / w.max(f32::EPSILON)).clamp(0.0, 1.0) * 255.0).round() as u8)
+        .collect())
+}
+
+// Tile origins along one axis, the last tile is aligned to the image border
+fn tile_positions(length: u32, tile_size: u32, overlap: u32) -> Vec<u32> {
+    let stride = (tile_size - overlap).max(1);
+    let mut positions: Vec<u32> = (0..length.saturating_sub(tile_size)).step_by(stride as usize).collect();
+    positions.push(length.saturating_sub(tile_size));
+    positions.dedup();
+    positions
+}
+
+// Blend weight of a tile pixel along one axis: ramps up over the overlap, except on the image borders.
+// Sampled at pixel centers, so the ramps of two tiles overlapping by `overlap` sum to 1.
+fn ramp(offset: u32, origin: u32, tile_size: u32, length: u32, overlap: u32) -> f32 {
+    if overlap == 0 {
+        return 1.0;
+    }
+    let from_start = if origin == 0 {
+        overlap as f32
+    } else {
+        offset as f32 + 0.5
+    };
+    let from_end = if origin + tile_size >= length {
+        overlap as f32
+    } else {
+        (tile_size - offset) as f32 - 0.5
+    };
+    from_start.min(from_end).min(overlap as f32) / overlap as f32
+}
+
+// `bgremoval tile <input> <output.png> [--tile-size N] [--overlap N] [--batch N]`
+pub fn tile_command(args: &[String]) -> Result<()> {
+    let [input, output, options @ ..] = args else {
+        return Err(anyhow::anyhow!(
+            "Usage: bgremoval tile <input.jpg|png> <output.png> [--tile-size N] [--overlap N] [--batch N]"
+        ));
+    };
+
+    let mut tiling = SETUP.tiling;
+    for option in options.chunks(2) {
+        match option {
+            [name, value] if name == "--tile-size" => tiling.tile_size = value.parse()?,
+            [name, value] if name == "--overlap" => tiling.overlap = value.parse()?,
+            [name, value] if name == "--batch" => tiling.max_batch = value.parse()?,
+            _ => return Err(anyhow::anyhow!("Unknown option: {:?}", option)),
+        }
+    }
+
+    bgremoval::init_ort()?;
+    let mut backend = backend::load(SETUP.model_path)?;
+
+    let frame = imageio::load_frame(Path::new(input))?;
+    println!(
+        "Loaded {} ({}x{}), tiling with {:?}",
+        input, frame.width, frame.height, tiling
+    );
+
+    let start = Instant::now();
+    let alpha = tiled_alpha(backend.as_mut(), &frame, tiling)?;
+    println!("Tiled inference done in {} ms", start.elapsed().as_millis());
+
+    imageio::save_alpha_png(Path::new(output), &alpha, frame.width as u32, frame.height as u32)?;
+    println!("Alpha matte written to {}", output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{StubBackend, StubMask};
+    use fast_image_resize::PixelType;
+
+    #[test]
+    fn tile_positions_cover_the_axis() {
+        assert_eq!(tile_positions(1000, 400, 100), [0, 300, 600]);
+        assert_eq!(
+            tile_positions(900, 400, 100),
+            [0, 300, 500],
+            "Last tile aligned to the border"
+        );
+        assert_eq!(tile_positions(300, 400, 100), [0], "Single tile larger than the axis");
+    }
+
+    #[test]
+    fn overlap_ramps_sum_to_one() {
+        let (length, tile_size, overlap) = (1000, 400, 100);
+        let positions = tile_positions(length, tile_size, overlap);
+        for pixel in 0..length {
+            let sum: f32 = positions
+                .iter()
+                .filter(|&&origin| (origin..origin + tile_size).contains(&pixel))
+                .map(|&origin| ramp(pixel - origin, origin, tile_size, length, overlap))
+                .sum();
+            assert!((sum - 1.0).abs() < 1e-6, "pixel {}: weights sum to {}", pixel, sum);
+        }
+    }
+
+    #[test]
+    fn uniform_frame_gives_uniform_alpha() {
+        let (width, height) = (1200, 700);
+        let frame = Frame {
+            width,
+            height,
+            pixel_type: PixelType::U8x4,
+            data: [153, 153, 153, 255].repeat((width * height) as usize),
+        };
+        let tiling = Tiling {
+            tile_size: 512,
+            overlap: 128,
+            max_batch: 4,
+        };
+        let alpha = tiled_alpha(&mut StubBackend::new(StubMask::Brightness), &frame, tiling).unwrap();
+        assert!(
+            alpha.iter().all(|&a| a.abs_diff(153) <= 1),
+            "Seams or gaps between tiles"
+        );
+    }
+}
@@ -1,9 +1,21 @@
 use anyhow::Result;
 use fast_image_resize::PixelType;
 use raylib::{prelude::*, texture::Image};
-use std::{sync::mpsc::Receiver, time::Instant};
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, Sender},
+    time::{Duration, Instant},
+};
 
 use crate::SETUP;
+use crate::bgremoval::{self, Control, Segmentation, Settings};
+use crate::capture::CameraControls;
+use crate::chroma::{self, KeyColor};
+use crate::compositor::{Background, Compositor, GpuCompositing};
+use crate::config::Config;
+use crate::imageio;
+use crate::overlay;
+use crate::panel::{PanelActions, SettingsPanel};
 
 #[derive(Clone, Debug)]
 pub struct Frame {
@@ -69,165 +81,837 @@ pub struct RaylibFrames {
     pub high_res_frame: Frame,
     pub low_res_frame: Frame,
     pub ml_low_frame: Frame,
-    pub ml_high_frame: Frame,
+    pub ml_high_frame: Option<Frame>, // None when the pipeline skips the full resolution mask, see `full_mask`
+    pub foreground: Option<Frame>,    // Estimated person colors, composited instead of the camera frame
     pub instant: Instant,
+    pub mask_age: Duration, // Time between the frame the mask was inferred on and this frame
 }
 
-pub fn start_raylib_viewer(rx: Receiver<RaylibFrames>) -> Result<()> {
-    let scale_factor = 0.5 as f32;
+impl RaylibFrames {
+    // RGBA viewer mask at the camera resolution, upsampled from the low resolution one when the
+    // pipeline did not build it
+    pub fn full_mask(&self) -> Result<Vec<u8>> {
+        match &self.ml_high_frame {
+            Some(mask) => Ok(mask.as_rgba()),
+            None => bgremoval::resize_mask(
+                self.ml_low_frame.width as u32,
+                self.ml_low_frame.height as u32,
+                &mut self.ml_low_frame.as_rgba(),
+                self.high_res_frame.width as u32,
+                self.high_res_frame.height as u32,
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraLayout {
+    Tiled,      // All cameras in a grid
+    Switchable, // One camera over the whole window, Tab or 1-9 switch camera
+}
+
+// What is drawn for each shown camera
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ViewLayout {
+    Composite,   // Composited frame only
+    SideBySide,  // Camera frame next to the composited frame
+    DebugGrid,   // Composited, camera, low resolution and mask frames in a 2x2 grid
+    MaskOverlay, // Camera frame with the person tinted, soft alpha highlighted and the mask contour
+}
+
+impl ViewLayout {
+    pub const ALL: [ViewLayout; 4] = [
+        ViewLayout::Composite,
+        ViewLayout::SideBySide,
+        ViewLayout::DebugGrid,
+        ViewLayout::MaskOverlay,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            ViewLayout::Composite => "composite",
+            ViewLayout::SideBySide => "side_by_side",
+            ViewLayout::DebugGrid => "debug_grid",
+            ViewLayout::MaskOverlay => "mask_overlay",
+        }
+    }
+
+    // Columns, rows and panes of the cell grid, in row order
+    fn panes(self) -> (usize, usize, &'static [Pane]) {
+        match self {
+            ViewLayout::Composite => (1, 1, &[Pane::Composite]),
+            ViewLayout::SideBySide => (2, 1, &[Pane::Camera, Pane::Composite]),
+            ViewLayout::DebugGrid => (2, 2, &[Pane::Composite, Pane::Camera, Pane::LowRes, Pane::Mask]),
+            ViewLayout::MaskOverlay => (1, 1, &[Pane::MaskOverlay]),
+        }
+    }
+}
+
+// One picture of a view layout. All of them show the whole camera field of view.
+#[derive(Clone, Copy)]
+enum Pane {
+    Composite,
+    Camera,
+    LowRes,
+    Mask,
+    MaskOverlay,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BackgroundMode {
+    Color, // Solid `background_color` behind the person
+    Blur,  // The camera frame itself, heavily blurred
+    Image, // One of the pictures of `background_images`, cropped to the frame aspect
+}
+
+impl BackgroundMode {
+    pub const ALL: [BackgroundMode; 3] = [BackgroundMode::Color, BackgroundMode::Blur, BackgroundMode::Image];
+
+    fn name(self) -> &'static str {
+        match self {
+            BackgroundMode::Color => "color",
+            BackgroundMode::Blur => "blur",
+            BackgroundMode::Image => "image",
+        }
+    }
+}
+
+// Initial window size relative to the full resolution frame, the window is resizable
+const WINDOW_SCALE: f32 = 0.5;
+
+// Downsampling factor of the low resolution frame for the blurred background, stretched back with
+// bilinear filtering
+const BACKGROUND_BLUR: usize = 16;
+
+// Width of the mask contour of the mask overlay view, in full resolution pixels
+const CONTOUR_WIDTH: u32 = 3;
+
+// Key bindings, shown by the help overlay
+const BINDINGS: &[(&str, &str)] = &[
+    ("H", "Show or hide this help"),
+    ("G", "Switch background mode"),
+    ("V", "Switch view layout"),
+    ("Up / Down", "Raise or lower the segmentation threshold"),
+    ("L", "Switch camera layout"),
+    ("Tab, 1-9", "Select camera"),
+    ("O", "Show or hide the text overlays"),
+    ("Space", "Freeze or resume the picture"),
+    ("Left click", "Pick the chroma key color"),
+    ("B", "Capture the background plate"),
+    ("M", "Reload the model"),
+    ("P", "Show or hide the settings panel"),
+    ("F11", "Toggle fullscreen"),
+];
+
+// Keys selecting the first nine cameras
+const CAMERA_KEYS: [KeyboardKey; 9] = [
+    KeyboardKey::KEY_ONE,
+    KeyboardKey::KEY_TWO,
+    KeyboardKey::KEY_THREE,
+    KeyboardKey::KEY_FOUR,
+    KeyboardKey::KEY_FIVE,
+    KeyboardKey::KEY_SIX,
+    KeyboardKey::KEY_SEVEN,
+    KeyboardKey::KEY_EIGHT,
+    KeyboardKey::KEY_NINE,
+];
+
+// Picture of `background_images`
+pub struct BackgroundImage {
+    pub name: String, // File name
+    texture: Texture2D,
+}
+
+// Viewer state changed by the key bindings and the settings panel
+pub struct ViewOptions {
+    pub layout: ViewLayout,
+    pub background: BackgroundMode,
+    pub background_color: [u8; 3],
+    pub background_image: usize, // Index in the loaded background images
+    pub overlays: bool,          // Labels and timings
+}
+
+impl ViewOptions {
+    pub fn from_setup() -> Self {
+        Self {
+            layout: SETUP.view_layout,
+            background: SETUP.background_mode,
+            background_color: SETUP.background_color,
+            background_image: 0,
+            overlays: true,
+        }
+    }
+
+    pub fn read_config(&mut self, config: &Config, images: &[BackgroundImage]) {
+        if let Some(name) = config.get("view_layout") {
+            match ViewLayout::ALL.into_iter().find(|layout| layout.name() == name) {
+                Some(layout) => self.layout = layout,
+                None => eprintln!("Config: unknown view_layout {}", name),
+            }
+        }
+        if let Some(name) = config.get("background_mode") {
+            match BackgroundMode::ALL.into_iter().find(|mode| mode.name() == name) {
+                Some(mode) => self.background = mode,
+                None => eprintln!("Config: unknown background_mode {}", name),
+            }
+        }
+        config.read_rgb("background_color", &mut self.background_color);
+        if let Some(name) = config.get("background_image") {
+            match images.iter().position(|image| image.name == name) {
+                Some(i) => self.background_image = i,
+                None => eprintln!("Config: background image {} not found", name),
+            }
+        }
+    }
+
+    pub fn write_config(&self, config: &mut Config, images: &[BackgroundImage]) {
+        config.set("view_layout", self.layout.name());
+        config.set("background_mode", self.background.name());
+        config.set_rgb("background_color", self.background_color);
+        if let Some(image) = images.get(self.background_image) {
+            config.set("background_image", &image.name);
+        }
+    }
+
+    // What goes behind the person, the image mode falls back to the color without images
+    fn backdrop<'a>(&self, images: &'a [BackgroundImage]) -> Backdrop<'a> {
+        let [r, g, b] = self.background_color;
+        match self.background {
+            BackgroundMode::Blur => Backdrop::Blur,
+            BackgroundMode::Image if !images.is_empty() => {
+                Backdrop::Image(&images[self.background_image.min(images.len() - 1)].texture)
+            }
+            _ => Backdrop::Color(Color::new(r, g, b, 255)),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Backdrop<'a> {
+    Color(Color),
+    Blur,
+    Image(&'a Texture2D),
+}
+
+// How the composited panes are drawn this frame
+struct Compositing<'a> {
+    backdrop: Backdrop<'a>,
+    shader: Option<&'a mut Compositor>, // None for the CPU blend
+}
+
+// Textures, latest frames and timings of one camera
+struct StreamView {
+    compositing: Option<GpuCompositing>, // None to blend on the CPU into `high_res_texture`
+    high_res_texture: Texture2D,         // Composited on the CPU
+    camera_texture: Texture2D,
+    foreground_texture: Texture2D, // Estimated person colors, for the shader
+    full_mask_texture: Texture2D,  // Refined mask, for the shader with `full_res_mask`
+    low_res_texture: Texture2D,
+    ml_res_texture: Texture2D,
+    blur_texture: Texture2D,
+    overlay_texture: Texture2D,
+    overlay_stale: bool, // The overlay is only rebuilt from the latest mask while it is shown
+    frames: RaylibFrames,
+    latency: Duration,
+}
+
+impl StreamView {
+    fn new(
+        rl: &mut RaylibHandle,
+        thread: &RaylibThread,
+        frames: RaylibFrames,
+        compositing: Option<GpuCompositing>,
+    ) -> Result<Self> {
+        let mut view = Self {
+            compositing,
+            high_res_texture: frame_texture(rl, thread, &frames.high_res_frame)?,
+            camera_texture: frame_texture(rl, thread, &frames.high_res_frame)?,
+            foreground_texture: frame_texture(rl, thread, &frames.high_res_frame)?,
+            full_mask_texture: frame_texture(rl, thread, &frames.high_res_frame)?,
+            low_res_texture: frame_texture(rl, thread, &frames.low_res_frame)?,
+            ml_res_texture: frame_texture(rl, thread, &frames.ml_low_frame)?,
+            blur_texture: frame_texture(rl, thread, &downsample(&frames.low_res_frame, BACKGROUND_BLUR))?,
+            overlay_texture: frame_texture(rl, thread, &frames.high_res_frame)?,
+            overlay_stale: true,
+            latency: Duration::ZERO,
+            frames: frames.clone(),
+        };
+        view.update(frames)?;
+        Ok(view)
+    }
+
+    // Uploads the frames. The shader composites from the textures as they are, only the CPU
+    // compositing blends here.
+    fn update(&mut self, frames: RaylibFrames) -> Result<()> {
+        match self.compositing {
+            None => {
+                let person = frames.foreground.as_ref().unwrap_or(&frames.high_res_frame);
+                self.high_res_texture
+                    .update_texture(&blend(&person.as_rgba(), &frames.full_mask()?))?;
+            }
+            Some(compositing) => {
+                if let Some(foreground) = &frames.foreground {
+                    upload(&mut self.foreground_texture, foreground)?;
+                }
+                if compositing.full_res_mask {
+                    self.full_mask_texture.update_texture(&frames.full_mask()?)?;
+                }
+            }
+        }
+        upload(&mut self.camera_texture, &frames.high_res_frame)?;
+        upload(&mut self.low_res_texture, &frames.low_res_frame)?;
+        upload(&mut self.ml_res_texture, &frames.ml_low_frame)?;
+        self.blur_texture
+            .update_texture(&downsample(&frames.low_res_frame, BACKGROUND_BLUR).data)?;
+        self.latency = frames.instant.elapsed();
+        self.frames = frames;
+        self.overlay_stale = true;
+        Ok(())
+    }
+
+    // Rebuilds the mask overlay from the full resolution mask of the latest frames
+    fn refresh_overlay(&mut self) -> Result<()> {
+        if !self.overlay_stale {
+            return Ok(());
+        }
+        let (width, height) = (self.frames.high_res_frame.width, self.frames.high_res_frame.height);
+        let alpha: Vec<u8> = self.frames.full_mask()?.chunks_exact(4).map(|m| 255 - m[3]).collect();
+        let overlay = overlay::mask_overlay(&alpha, width as usize, height as usize, CONTOUR_WIDTH);
+        self.overlay_texture.update_texture(&overlay)?;
+        self.overlay_stale = false;
+        Ok(())
+    }
+
+    // Panes of `layout` in a cell, each fitted to its slot of the layout grid with its aspect ratio
+    fn panes(&self, cell: Rectangle, layout: ViewLayout) -> Vec<(Pane, Rectangle)> {
+        let (columns, rows, panes) = layout.panes();
+        let (slot_width, slot_height) = (cell.width / columns as f32, cell.height / rows as f32);
+        panes
+            .iter()
+            .enumerate()
+            .map(|(i, &pane)| {
+                let slot = Rectangle::new(
+                    cell.x + (i % columns) as f32 * slot_width,
+                    cell.y + (i / columns) as f32 * slot_height,
+                    slot_width,
+                    slot_height,
+                );
+                (pane, fit_rect(self.texture(pane), slot))
+            })
+            .collect()
+    }
+
+    fn texture(&self, pane: Pane) -> &Texture2D {
+        match pane {
+            Pane::Composite => &self.high_res_texture,
+            Pane::Camera | Pane::MaskOverlay => &self.camera_texture,
+            Pane::LowRes => &self.low_res_texture,
+            Pane::Mask => &self.ml_res_texture,
+        }
+    }
+
+    // Background then person, in the shader or over the CPU composited frame
+    fn draw_composite(&self, d: &mut RaylibDrawHandle, rect: Rectangle, compositing: &mut Compositing) {
+        let background = match compositing.backdrop {
+            Backdrop::Color(color) => Background::Color(color),
+            Backdrop::Blur => Background::Texture(&self.blur_texture, texture_rect(&self.blur_texture)),
+            Backdrop::Image(texture) => Background::Texture(texture, cover_rect(texture, rect.width / rect.height)),
+        };
+        if let Some(compositor) = &mut compositing.shader {
+            let person = match self.frames.foreground {
+                Some(_) => &self.foreground_texture,
+                None => &self.camera_texture,
+            };
+            let mask = if self.compositing.is_some_and(|compositing| compositing.full_res_mask) {
+                &self.full_mask_texture
+            } else {
+                &self.ml_res_texture
+            };
+            compositor.draw(d, person, mask, background, rect);
+            return;
+        }
+
+        match background {
+            Background::Color(color) => d.draw_rectangle_rec(rect, color),
+            Background::Texture(texture, source) => {
+                d.draw_texture_pro(texture, source, rect, Vector2::new(0.0, 0.0), 0.0, Color::WHITE)
+            }
+        }
+        draw_texture_in(d, &self.high_res_texture, rect, Color::WHITE);
+    }
+
+    // Panes of `layout` fitted into a cell, with the label and timings in its corner
+    fn draw(
+        &self,
+        d: &mut RaylibDrawHandle,
+        font: &Font,
+        label: &str,
+        cell: Rectangle,
+        options: &ViewOptions,
+        compositing: &mut Compositing,
+    ) {
+        for (pane, rect) in self.panes(cell, options.layout) {
+            match pane {
+                Pane::Composite => self.draw_composite(d, rect, compositing),
+                Pane::MaskOverlay => {
+                    draw_texture_in(d, &self.camera_texture, rect, Color::WHITE);
+                    draw_texture_in(d, &self.overlay_texture, rect, Color::WHITE);
+                }
+                _ => draw_texture_in(d, self.texture(pane), rect, Color::WHITE),
+            }
+        }
+        if !options.overlays {
+            return;
+        }
+
+        let lines = [
+            format!(
+                "{} - High Res: {}x{} with upscaled mask: from {}x{}",
+                label,
+                self.high_res_texture.width(),
+                self.high_res_texture.height(),
+                self.ml_res_texture.width(),
+                self.ml_res_texture.height()
+            ),
+            format!("Total elaboration and render time: {} ms", self.latency.as_millis()),
+            format!("Mask age: {} ms", self.frames.mask_age.as_millis()),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            d.draw_text_ex(
+                font,
+                line,
+                Vector2::new(cell.x + 10.0, cell.y + 10.0 + 28.0 * i as f32),
+                24.0,
+                1.0,
+                Color::BLUE,
+            );
+        }
+    }
 
+    // Key color around a window position inside `rect`, where a pane is drawn. Read from the low
+    // resolution frame, which has no spill suppression.
+    fn pick_key_color(&self, rect: Rectangle, position: Vector2) -> Option<[u8; 3]> {
+        if !rect.check_collision_point_rec(position) {
+            return None;
+        }
+        let frame = &self.frames.low_res_frame;
+        let x = ((position.x - rect.x) / rect.width * frame.width as f32) as u32;
+        let y = ((position.y - rect.y) / rect.height * frame.height as f32) as u32;
+        Some(chroma::pick_key_color(
+            frame,
+            x.min(frame.width as u32 - 1),
+            y.min(frame.height as u32 - 1),
+            4,
+        ))
+    }
+}
+
+fn frame_texture(rl: &mut RaylibHandle, thread: &RaylibThread, frame: &Frame) -> Result<Texture2D> {
+    let texture =
+        rl.load_texture_from_image(thread, &Image::gen_image_color(frame.width, frame.height, Color::WHITE))?;
+    texture.set_texture_filter(thread, raylib::consts::TextureFilter::TEXTURE_FILTER_BILINEAR);
+    Ok(texture)
+}
+
+fn texture_rect(texture: &Texture2D) -> Rectangle {
+    Rectangle::new(0.0, 0.0, texture.width() as f32, texture.height() as f32)
+}
+
+// Uploads a frame, without a copy when it is RGBA already
+fn upload(texture: &mut Texture2D, frame: &Frame) -> Result<()> {
+    match frame.pixel_type {
+        PixelType::U8x4 => texture.update_texture(&frame.data)?,
+        _ => texture.update_texture(&frame.as_rgba())?,
+    }
+    Ok(())
+}
+
+// Whole texture stretched over `rect`
+fn draw_texture_in(d: &mut RaylibDrawHandle, texture: &Texture2D, rect: Rectangle, tint: Color) {
+    d.draw_texture_pro(texture, texture_rect(texture), rect, Vector2::new(0.0, 0.0), 0.0, tint);
+}
+
+// Largest rectangle with the aspect ratio of the texture centered in `cell`
+fn fit_rect(texture: &Texture2D, cell: Rectangle) -> Rectangle {
+    let (width, height) = (texture.width() as f32, texture.height() as f32);
+    let scale = (cell.width / width).min(cell.height / height);
+    Rectangle::new(
+        cell.x + (cell.width - width * scale) * 0.5,
+        cell.y + (cell.height - height * scale) * 0.5,
+        width * scale,
+        height * scale,
+    )
+}
+
+// Centered part of a texture with the given aspect ratio (width / height)
+fn cover_rect(texture: &Texture2D, aspect: f32) -> Rectangle {
+    let (width, height) = (texture.width() as f32, texture.height() as f32);
+    let (crop_width, crop_height) = if width / height > aspect {
+        (height * aspect, height)
+    } else {
+        (width, width / aspect)
+    };
+    Rectangle::new(
+        (width - crop_width) * 0.5,
+        (height - crop_height) * 0.5,
+        crop_width,
+        crop_height,
+    )
+}
+
+// Pictures of the `background_images` directory, in file name order
+fn load_background_images(rl: &mut RaylibHandle, thread: &RaylibThread) -> Vec<BackgroundImage> {
+    let Some(directory) = SETUP.background_images else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir(directory) {
+        Ok(entries) => entries.filter_map(|entry| Some(entry.ok()?.path())).collect(),
+        Err(e) => {
+            eprintln!("No background images in {}: {:?}", directory, e);
+            return Vec::new();
+        }
+    };
+    paths.sort();
+
+    paths
+        .into_iter()
+        .filter(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| ["jpg", "jpeg", "png"].contains(&extension.to_ascii_lowercase().as_str()))
+        })
+        .filter_map(|path| {
+            let texture = imageio::load_frame(&path).and_then(|frame| {
+                let mut texture = frame_texture(rl, thread, &frame)?;
+                texture.update_texture(&frame.data)?;
+                Ok(texture)
+            });
+            match texture {
+                Ok(texture) => Some(BackgroundImage {
+                    name: path.file_name()?.to_string_lossy().to_string(),
+                    texture,
+                }),
+                Err(e) => {
+                    eprintln!("Failed to load background {}: {:?}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+// RGBA frame of the means of `factor` x `factor` pixel blocks
+fn downsample(frame: &Frame, factor: usize) -> Frame {
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let (small_width, small_height) = ((width / factor).max(1), (height / factor).max(1));
+    let channels = frame.pixel_type.size();
+    let mut data = Vec::with_capacity(small_width * small_height * 4);
+    for sy in 0..small_height {
+        for sx in 0..small_width {
+            let mut sum = [0u32; 3];
+            let rows = sy * height / small_height..(sy + 1) * height / small_height;
+            let columns = sx * width / small_width..(sx + 1) * width / small_width;
+            let count = (rows.len() * columns.len()).max(1) as u32;
+            for y in rows {
+                for x in columns.clone() {
+                    let px = &frame.data[(y * width + x) * channels..];
+                    for (sum, &value) in sum.iter_mut().zip(px) {
+                        *sum += value as u32;
+                    }
+                }
+            }
+            data.extend(sum.map(|s| (s / count) as u8));
+            data.push(255);
+        }
+    }
+    Frame {
+        width: small_width as i32,
+        height: small_height as i32,
+        pixel_type: PixelType::U8x4,
+        data,
+    }
+}
+
+// One receiver per camera, with its label and controls. The key bindings of `BINDINGS` and the
+// settings panel change the view and send their requests to the inference stage through `controls`.
+pub fn start_raylib_viewer(
+    streams: Vec<(&'static str, Receiver<RaylibFrames>)>,
+    controls: Sender<Control>,
+    mut cameras: Vec<CameraControls>,
+) -> Result<()> {
     let (mut rl, thread) = raylib::init()
         .size(
-            (SETUP.full_dec_width as f32 * scale_factor) as i32,
-            ((SETUP.full_dec_height + SETUP.small_dec_height) as f32 * scale_factor) as i32,
+            (SETUP.full_dec_width as f32 * WINDOW_SCALE) as i32,
+            (SETUP.full_dec_height as f32 * WINDOW_SCALE) as i32,
         )
         .title("Camera Stream")
+        .resizable()
         .log_level(raylib::consts::TraceLogLevel::LOG_ALL)
         .build();
+    rl.set_window_min_size(320, 180);
     rl.set_target_fps(60);
 
     let font = rl.load_font(&thread, "fonts/Roboto-Regular.ttf").unwrap();
     font.texture()
         .set_texture_filter(&thread, raylib::consts::TextureFilter::TEXTURE_FILTER_BILINEAR);
+    rl.gui_set_font(&font);
+    rl.gui_set_style(GuiControl::DEFAULT, GuiDefaultProperty::TEXT_SIZE, 18);
+    let images = load_background_images(&mut rl, &thread);
+    let mut compositor = SETUP
+        .gpu_compositing
+        .and_then(|options| Compositor::load(&mut rl, &thread, options));
 
-    let Ok(RaylibFrames {
-        high_res_frame,
-        low_res_frame,
-        ml_low_frame: ml_frame,
-        ml_high_frame: _ml_high_frame,
-        instant,
-    }) = rx.recv()
-    else {
-        return Err(anyhow::anyhow!("Failed to receive initial setup frame"));
-    };
-
-    println!(
-        "Initial frame received in {} ms, starting viewer...",
-        instant.elapsed().as_millis()
-    );
+    // Saved settings over the `SETUP` defaults, the inference stage loads the same file
+    let config = Config::load(Path::new(SETUP.settings_path)).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {:?}", SETUP.settings_path, e);
+        Config::default()
+    });
+    let mut settings = Settings::from_setup();
+    settings.read_config(&config);
+    let mut options = ViewOptions::from_setup();
+    options.read_config(&config, &images);
+    for (source, camera) in cameras.iter_mut().enumerate() {
+        camera.read_config(&config, source);
+    }
 
-    // Create high resolution image
-    let mut high_res_texture = rl.load_texture_from_image(
-        &thread,
-        &Image::gen_image_color(high_res_frame.width, high_res_frame.height, Color::WHITE),
-    )?;
-    high_res_texture.set_texture_filter(&thread, raylib::consts::TextureFilter::TEXTURE_FILTER_BILINEAR);
-    high_res_texture.update_texture(&blend(&high_res_frame.data, &_ml_high_frame.data))?;
-
-    // Create high resolution image
-    let mut low_res_texture = rl.load_texture_from_image(
-        &thread,
-        &Image::gen_image_color(low_res_frame.width, low_res_frame.height, Color::WHITE),
-    )?;
-    low_res_texture.set_texture_filter(&thread, raylib::consts::TextureFilter::TEXTURE_FILTER_BILINEAR);
-    low_res_texture.update_texture(&low_res_frame.data)?;
-
-    // Create ML processed image
-    let mut ml_res_texture = rl.load_texture_from_image(
-        &thread,
-        &Image::gen_image_color(ml_frame.width, ml_frame.height, Color::WHITE),
-    )?;
-    ml_res_texture.set_texture_filter(&thread, raylib::consts::TextureFilter::TEXTURE_FILTER_BILINEAR);
-    ml_res_texture.update_texture(&ml_frame.as_rgba())?;
+    let start = Instant::now();
+    let mut views: Vec<Option<StreamView>> = streams.iter().map(|_| None).collect();
+    let mut selected = 0;
+    let mut layout = SETUP.camera_layout;
+    let mut panel = SettingsPanel::default();
+    let mut frozen = false;
+    let mut help = false;
 
     while !rl.window_should_close() {
-        let mut d = rl.begin_drawing(&thread);
+        let previous_settings = settings;
+        // Keep only the latest frames of every camera, textures are created on the first one. While
+        // frozen the frames are still drained so that the pipeline does not queue up.
+        for ((label, rx), view) in streams.iter().zip(views.iter_mut()) {
+            let Some(frames) = rx.try_iter().last() else {
+                continue;
+            };
+            match view {
+                Some(_) if frozen => {}
+                Some(view) => view.update(frames)?,
+                None => {
+                    println!(
+                        "{}: initial frame received after {} ms",
+                        label,
+                        start.elapsed().as_millis()
+                    );
+                    let compositing = compositor.as_ref().map(|compositor| compositor.options);
+                    *view = Some(StreamView::new(&mut rl, &thread, frames, compositing)?);
+                }
+            }
+        }
 
-        d.clear_background(Color::BLACK);
-        d.draw_texture_ex(
-            &high_res_texture,
-            Vector2::new(0.0, 0.0),
-            0.0,
-            scale_factor,
-            Color::WHITE,
-        );
-        d.draw_texture_ex(
-            &low_res_texture,
-            Vector2::new(0 as f32, high_res_frame.height as f32 * scale_factor),
-            0.0,
-            scale_factor,
-            Color::WHITE,
-        );
-        d.draw_texture_ex(
-            &ml_res_texture,
-            Vector2::new(
-                low_res_frame.width as f32 * scale_factor,
-                high_res_frame.height as f32 * scale_factor,
-            ),
-            0.0,
-            scale_factor,
-            Color::WHITE,
-        );
-        d.draw_text_ex(
-            &font,
-            &format!(
-                "High Res: {}x{} with upscaled mask: from {}x{}",
-                high_res_frame.width, high_res_frame.height, ml_frame.width, ml_frame.height
-            ),
-            Vector2::new(10.0, 10.0),
-            30.0,
-            1.0,
-            Color::BLUE,
-        );
-        match rx.recv() {
-            Ok(RaylibFrames {
-                high_res_frame,
-                low_res_frame,
-                ml_low_frame,
-                ml_high_frame,
-                instant,
-            }) => {
-                // Create high resolution image
-                high_res_texture.update_texture(&blend(&high_res_frame.as_rgba(), &ml_high_frame.as_rgba()))?;
-                d.draw_text_ex(
-                    &font,
-                    &format!(
-                        "Total elaboration and render time: {} ms",
-                        instant.elapsed().as_millis(),
-                    ),
-                    Vector2::new(10.0, 40.0),
-                    30.0,
-                    1.0,
-                    Color::BLUE,
-                );
+        if rl.is_key_pressed(KeyboardKey::KEY_H) {
+            help = !help;
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_G) {
+            options.background = match options.background {
+                BackgroundMode::Color => BackgroundMode::Blur,
+                BackgroundMode::Blur => BackgroundMode::Image,
+                BackgroundMode::Image => BackgroundMode::Color,
+            };
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_V) {
+            let next = ViewLayout::ALL
+                .iter()
+                .position(|&layout| layout == options.layout)
+                .unwrap_or(0)
+                + 1;
+            options.layout = ViewLayout::ALL[next % ViewLayout::ALL.len()];
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_F11) {
+            // Borderless window over the whole monitor, which keeps the desktop resolution
+            rl.toggle_borderless_windowed();
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_L) {
+            layout = match layout {
+                CameraLayout::Switchable => CameraLayout::Tiled,
+                CameraLayout::Tiled => CameraLayout::Switchable,
+            };
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_O) {
+            options.overlays = !options.overlays;
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_SPACE) {
+            frozen = !frozen;
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_P) {
+            panel.open = !panel.open;
+        }
+        let steps = rl.is_key_pressed(KeyboardKey::KEY_UP) as i32 - rl.is_key_pressed(KeyboardKey::KEY_DOWN) as i32;
+        if steps != 0 {
+            println!("Threshold {}", settings.adjust_threshold(steps));
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_M) {
+            println!("Reloading {}", SETUP.model_path);
+            controls.send(Control::LoadModel(PathBuf::from(SETUP.model_path)))?;
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_TAB) {
+            selected = (selected + 1) % streams.len();
+        }
+        if let Some(camera) = CAMERA_KEYS.iter().position(|&key| rl.is_key_pressed(key))
+            && camera < streams.len()
+        {
+            selected = camera;
+        }
 
-                // SAFELY grab the default Font from the C API:
-
-                let scaling_text = format!("Raylib UI scaling factor: {}", scale_factor);
-                d.draw_text_ex(
-                    &font,
-                    &scaling_text,
-                    Vector2::new(
-                        (d.get_render_width() - raylib::core::RaylibHandle::measure_text(&d, &scaling_text, 30)) as f32,
-                        (d.get_render_height() - 35) as f32,
-                    ),
-                    30.0,
-                    1.0,
-                    Color::BLUE,
-                );
+        // Cells of the shown cameras, recomputed every frame so that the picture follows window resizes
+        let (width, height) = (rl.get_render_width() as f32, rl.get_render_height() as f32);
+        let panel_bounds = SettingsPanel::bounds(width, height);
+        let cells: Vec<(usize, Rectangle)> = match layout {
+            CameraLayout::Switchable => vec![(selected, Rectangle::new(0.0, 0.0, width, height))],
+            CameraLayout::Tiled => {
+                let columns = (views.len() as f32).sqrt().ceil().max(1.0);
+                let rows = (views.len() as f32 / columns).ceil().max(1.0);
+                let (cell_width, cell_height) = (width / columns, height / rows);
+                (0..views.len())
+                    .map(|i| {
+                        let cell = Rectangle::new(
+                            (i as f32 % columns).floor() * cell_width,
+                            (i as f32 / columns).floor() * cell_height,
+                            cell_width,
+                            cell_height,
+                        );
+                        (i, cell)
+                    })
+                    .collect()
+            }
+        };
+
+        if options.layout == ViewLayout::MaskOverlay {
+            for &(i, _) in &cells {
+                if let Some(view) = &mut views[i] {
+                    view.refresh_overlay()?;
+                }
+            }
+        }
+
+        if rl.is_key_pressed(KeyboardKey::KEY_B) {
+            for &(source, _) in &cells {
+                controls.send(Control::CapturePlate(source))?;
+            }
+        }
+        let position = rl.get_mouse_position();
+        if rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT)
+            && !(panel.open && panel_bounds.check_collision_point_rec(position))
+            && let Segmentation::ChromaKey(chroma_key) = &mut settings.segmentation
+        {
+            let picked = cells.iter().find_map(|&(i, cell)| {
+                let view = views[i].as_ref()?;
+                view.panes(cell, options.layout)
+                    .into_iter()
+                    .find_map(|(_, rect)| view.pick_key_color(rect, position))
+            });
+            if let Some(rgb) = picked {
+                println!("Key color {:?}", rgb);
+                chroma_key.key = KeyColor::Fixed(rgb);
+            }
+        }
 
-                // Create low resolution image
-                low_res_texture.update_texture(&low_res_frame.as_rgba())?;
-                // Create ML processed image
-                ml_res_texture.update_texture(&ml_low_frame.as_rgba())?;
+        let mut d = rl.begin_drawing(&thread);
+        d.clear_background(Color::BLACK);
+
+        let mut compositing = Compositing {
+            backdrop: options.backdrop(&images),
+            shader: compositor.as_mut(),
+        };
+        for &(i, cell) in &cells {
+            let label = streams[i].0;
+            match &views[i] {
+                Some(view) => view.draw(&mut d, &font, label, cell, &options, &mut compositing),
+                None => draw_waiting(&mut d, &font, label, Vector2::new(cell.x + 10.0, cell.y + 10.0)),
             }
-            Err(_) => {}
+        }
+        if frozen {
+            d.draw_text_ex(
+                &font,
+                "Frozen",
+                Vector2::new(10.0, height - 35.0),
+                30.0,
+                1.0,
+                Color::RED,
+            );
+        }
+        let actions = if panel.open {
+            panel.draw(
+                &mut d,
+                panel_bounds,
+                &mut settings,
+                &mut options,
+                &images,
+                (streams[selected].0, &mut cameras[selected]),
+            )
+        } else {
+            PanelActions::default()
         };
+        if help {
+            draw_help(&mut d, &font);
+        }
+
+        if actions.save {
+            let mut config = Config::default();
+            settings.write_config(&mut config);
+            options.write_config(&mut config, &images);
+            for (source, camera) in cameras.iter().enumerate() {
+                camera.write_config(&mut config, source);
+            }
+            match config.save(Path::new(SETUP.settings_path)) {
+                Ok(()) => println!("Settings saved to {}", SETUP.settings_path),
+                Err(e) => eprintln!("Failed to save {}: {:?}", SETUP.settings_path, e),
+            }
+        }
+        if actions.reset {
+            settings = Settings::from_setup();
+            options = ViewOptions {
+                overlays: options.overlays,
+                ..ViewOptions::from_setup()
+            };
+            for camera in &mut cameras {
+                camera.reset();
+            }
+        }
+        if settings != previous_settings {
+            controls.send(Control::Settings(settings))?;
+        }
     }
     Ok(())
 }
 
+// Key bindings over a darkened window
+fn draw_help(d: &mut RaylibDrawHandle, font: &Font) {
+    let (width, height) = (d.get_render_width() as f32, d.get_render_height() as f32);
+    d.draw_rectangle_rec(Rectangle::new(0.0, 0.0, width, height), Color::BLACK.alpha(0.7));
+    for (i, (key, action)) in BINDINGS.iter().enumerate() {
+        let y = 40.0 + 34.0 * i as f32;
+        d.draw_text_ex(font, key, Vector2::new(40.0, y), 28.0, 1.0, Color::YELLOW);
+        d.draw_text_ex(font, action, Vector2::new(220.0, y), 28.0, 1.0, Color::WHITE);
+    }
+}
+
+fn draw_waiting(d: &mut RaylibDrawHandle, font: &Font, label: &str, position: Vector2) {
+    d.draw_text_ex(
+        font,
+        &format!("{}: waiting for frames...", label),
+        position,
+        30.0,
+        1.0,
+        Color::BLUE,
+    );
+}
+
+// CPU compositing of the person pixels with the viewer mask into an RGBA frame, for the viewer
+// without `gpu_compositing` or its shader, and checked by the golden tests
 pub fn blend(image: &[u8], mask: &[u8]) -> Vec<u8> {
     assert_eq!(image.len(), mask.len());
     let mut blended = Vec::with_capacity(image.len());
     for (px, m) in image.chunks_exact(4).zip(mask.chunks_exact(4)) {
-        if m[3] == 0 {
-            // Transparent mask alpha = person pixel: keep original pixel
-            blended.extend_from_slice(px);
-        } else {
-            // Opaque mask alpha = background pixel: use mask color (green here)
-            blended.extend_from_slice(&[0, 0, 0, 0]);
-        }
+        // Mask alpha is the background coverage: 0 keeps the person pixel, 255 drops it
+        let keep = 255 - m[3] as u16;
+        blended.extend_from_slice(&[px[0], px[1], px[2], (px[3] as u16 * keep / 255) as u8]);
     }
     blended
 }
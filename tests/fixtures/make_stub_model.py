@@ -0,0 +1,64 @@
+#!/usr/bin/env python3
+"""Writes stub_model.onnx, a deterministic stand-in for the segmentation model used by the
+golden image tests: output = mean of the RGB channels, so bright pixels count as person.
+
+input  "input"  float32 [N, 3, 512, 512]
+output "output" float32 [N, 1, 512, 512]
+
+Plain protobuf encoding, no onnx package needed.
+"""
+from pathlib import Path
+
+
+def varint(value):
+    out = bytearray()
+    while True:
+        byte = value & 0x7F
+        value >>= 7
+        if value:
+            out.append(byte | 0x80)
+        else:
+            out.append(byte)
+            return bytes(out)
+
+
+def field_varint(number, value):
+    return varint(number << 3) + varint(value)
+
+
+def field_bytes(number, payload):
+    if isinstance(payload, str):
+        payload = payload.encode()
+    return varint(number << 3 | 2) + varint(len(payload)) + payload
+
+
+def tensor_value_info(name, dims):
+    shape = b"".join(
+        field_bytes(1, field_bytes(2, dim) if isinstance(dim, str) else field_varint(1, dim)) for dim in dims
+    )
+    tensor_type = field_varint(1, 1) + field_bytes(2, shape)  # elem_type FLOAT
+    return field_bytes(1, name) + field_bytes(2, field_bytes(1, tensor_type))
+
+
+node = (
+    field_bytes(1, "input")
+    + field_bytes(2, "output")
+    + field_bytes(3, "channel_mean")
+    + field_bytes(4, "ReduceMean")
+    + field_bytes(5, field_bytes(1, "axes") + field_varint(8, 1) + field_varint(20, 7))  # INTS [1]
+    + field_bytes(5, field_bytes(1, "keepdims") + field_varint(3, 1) + field_varint(20, 2))  # INT 1
+)
+graph = (
+    field_bytes(1, node)
+    + field_bytes(2, "stub")
+    + field_bytes(11, tensor_value_info("input", ["N", 3, 512, 512]))
+    + field_bytes(12, tensor_value_info("output", ["N", 1, 512, 512]))
+)
+model = (
+    field_varint(1, 7)  # ir_version
+    + field_bytes(2, "bgremoval-tests")
+    + field_bytes(7, graph)
+    + field_bytes(8, field_bytes(1, "") + field_varint(2, 13))  # opset 13
+)
+
+Path(__file__).with_name("stub_model.onnx").write_bytes(model)
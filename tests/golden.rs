@@ -0,0 +1,239 @@
+//! Golden image regression tests for the whole pipeline.
+//!
+//! Fixed JPEG frames go through `decode`, the inference stage running a deterministic stub model
+//! (channel mean, see tests/fixtures/make_stub_model.py) on the CPU provider, the mask upsampling
+//! (`resize_mask`) or refinement and `blend`, with fixed settings rather than the `SETUP` defaults.
+//! The results are reduced to thumbnails and compared with tests/golden with a tolerance. A missing
+//! golden fails the test; after an intended change rewrite them with
+//! `BGREMOVAL_BLESS=1 cargo test --test golden` and commit them.
+
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::sync::mpsc::channel;
+use std::thread;
+
+use bgremoval::backend::OrtBackend;
+use bgremoval::bgremoval::{self as inference, InferenceCadence, Provider, Segmentation, Settings};
+use bgremoval::decoder;
+use bgremoval::refine::MaskRefinement;
+use bgremoval::viewer::{self, RaylibFrames};
+
+const THUMBNAIL_SCALE: usize = 8;
+const MAX_MEAN_DIFF: f32 = 1.0; // Mean absolute difference per value
+const OUTLIER_DIFF: u8 = 16;
+const MAX_OUTLIERS: f32 = 0.005; // Fraction of values off by more than OUTLIER_DIFF
+
+// Refinements with their own mask goldens, next to the plain upsampling
+const REFINEMENTS: [(&str, MaskRefinement); 2] = [
+    (
+        "guided",
+        MaskRefinement::GuidedFilter {
+            radius: 16,
+            eps: 1e-3,
+            subsample: 4,
+        },
+    ),
+    (
+        "matting",
+        MaskRefinement::Matting {
+            band: 8,
+            chroma_key: None,
+        },
+    ),
+];
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+// Every frame inferred and refined with `mask_refinement` as is, so the goldens only depend on the
+// stages under test
+fn test_settings(mask_refinement: MaskRefinement) -> Settings {
+    Settings {
+        mask_threshold: 235,
+        segmentation: Segmentation::Model,
+        feather: 0,
+        temporal_smoothing: 0.0,
+        inference_cadence: InferenceCadence::EveryFrame,
+        mask_postprocess: &[],
+        mask_refinement,
+        foreground_estimation: None,
+        roi_tracking: None,
+        motion_compensation: false,
+        async_inference: false,
+        full_res_mask: true,
+    }
+}
+
+// Runs one JPEG frame through decoder and inference stage, returns the viewer frames
+fn run_pipeline(jpeg: &str, mask_refinement: MaskRefinement) -> RaylibFrames {
+    static ORT: Once = Once::new();
+    ORT.call_once(|| inference::init_ort().expect("ONNX Runtime initialization failed"));
+
+    let (tx, rx) = channel();
+    let (ml_tx, ml_rx) = channel();
+    let (raylib_tx, raylib_rx) = channel();
+    let (_, control_rx) = channel(); // No model swaps or plate captures
+
+    let decoder = thread::spawn(move || decoder::decode(rx, ml_tx, 0));
+    tx.send(std::fs::read(fixture(jpeg)).unwrap()).unwrap();
+    drop(tx);
+
+    let session = inference::open_session(&fixture("stub_model.onnx").to_string_lossy(), Provider::Cpu).unwrap();
+    let backend = Box::new(OrtBackend::new(session));
+    inference::serve(backend, test_settings(mask_refinement), ml_rx, vec![raylib_tx], control_rx).unwrap();
+    decoder.join().unwrap().unwrap();
+
+    raylib_rx.recv().expect("No frame out of the pipeline")
+}
+
+// Person alpha (255 = person) of an RGBA viewer mask
+fn mask_alpha(mask: &[u8]) -> Vec<u8> {
+    mask.chunks_exact(4).map(|m| 255 - m[3]).collect()
+}
+
+// Box filtered thumbnail of an image with `channels` interleaved channels
+fn thumbnail(data: &[u8], width: usize, height: usize, channels: usize) -> (Vec<u8>, usize, usize) {
+    let (thumb_width, thumb_height) = (width / THUMBNAIL_SCALE, height / THUMBNAIL_SCALE);
+    let mut thumb = Vec::with_capacity(thumb_width * thumb_height * channels);
+    for ty in 0..thumb_height {
+        for tx in 0..thumb_width {
+            for c in 0..channels {
+                let mut sum = 0u32;
+                for y in ty * THUMBNAIL_SCALE..(ty + 1) * THUMBNAIL_SCALE {
+                    for x in tx * THUMBNAIL_SCALE..(tx + 1) * THUMBNAIL_SCALE {
+                        sum += data[(y * width + x) * channels + c] as u32;
+                    }
+                }
+                thumb.push((sum / (THUMBNAIL_SCALE * THUMBNAIL_SCALE) as u32) as u8);
+            }
+        }
+    }
+    (thumb, thumb_width, thumb_height)
+}
+
+// Binary PGM (1 channel) or PPM (3 channels)
+fn write_pnm(path: &Path, data: &[u8], width: usize, height: usize, channels: usize) {
+    let magic = if channels == 1 { "P5" } else { "P6" };
+    let mut bytes = format!("{}\n{} {}\n255\n", magic, width, height).into_bytes();
+    bytes.extend_from_slice(data);
+    std::fs::write(path, bytes).unwrap();
+}
+
+fn read_pnm(path: &Path) -> (Vec<u8>, usize, usize) {
+    let bytes = std::fs::read(path).unwrap();
+    // Header: magic, width, height, maxval, each followed by a single whitespace
+    let mut fields = Vec::new();
+    let mut start = 0;
+    for (i, &b) in bytes.iter().enumerate() {
+        if b.is_ascii_whitespace() {
+            fields.push(String::from_utf8_lossy(&bytes[start..i]).to_string());
+            start = i + 1;
+            if fields.len() == 4 {
+                break;
+            }
+        }
+    }
+    (bytes[start..].to_vec(), fields[1].parse().unwrap(), fields[2].parse().unwrap())
+}
+
+// Compares the thumbnail of an image with its golden, or rewrites the golden when blessing
+fn check_golden(name: &str, data: &[u8], width: usize, height: usize, channels: usize) {
+    let (thumb, thumb_width, thumb_height) = thumbnail(data, width, height, channels);
+    let extension = if channels == 1 { "pgm" } else { "ppm" };
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.{}", name, extension));
+
+    if std::env::var_os("BGREMOVAL_BLESS").is_some() {
+        std::fs::create_dir_all(golden.parent().unwrap()).unwrap();
+        write_pnm(&golden, &thumb, thumb_width, thumb_height, channels);
+        eprintln!("Wrote golden {}", golden.display());
+        return;
+    }
+    assert!(
+        golden.exists(),
+        "{}: missing golden {}, run with BGREMOVAL_BLESS=1 to write it",
+        name,
+        golden.display()
+    );
+
+    let (expected, expected_width, expected_height) = read_pnm(&golden);
+    assert_eq!(
+        (expected_width, expected_height),
+        (thumb_width, thumb_height),
+        "{}: size differs from the golden",
+        name
+    );
+
+    let diffs: Vec<u8> = thumb.iter().zip(expected.iter()).map(|(&a, &b)| a.abs_diff(b)).collect();
+    let mean = diffs.iter().map(|&d| d as f32).sum::<f32>() / diffs.len() as f32;
+    let outliers = diffs.iter().filter(|&&d| d > OUTLIER_DIFF).count() as f32 / diffs.len() as f32;
+    if mean > MAX_MEAN_DIFF || outliers > MAX_OUTLIERS {
+        let actual = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.actual.{}", name, extension));
+        write_pnm(&actual, &thumb, thumb_width, thumb_height, channels);
+        panic!(
+            "{}: mean difference {:.3}, {:.2}% outliers against {}, actual written to {}",
+            name,
+            mean,
+            outliers * 100.0,
+            golden.display(),
+            actual.display()
+        );
+    }
+}
+
+// Checks a fixture against its goldens: the full resolution mask of the upsampled viewer frames,
+// their `blend` composite over black, and the mask of the fixture run again with each refinement
+fn check_fixture(name: &str, frames: &RaylibFrames) {
+    let (width, height) = (frames.high_res_frame.width as usize, frames.high_res_frame.height as usize);
+
+    let alpha = mask_alpha(&frames.full_mask().unwrap());
+    check_golden(&format!("{}_mask", name), &alpha, width, height, 1);
+
+    for (refinement_name, refinement) in REFINEMENTS {
+        let refined = run_pipeline(&format!("{}.jpg", name), refinement);
+        let alpha = mask_alpha(&refined.full_mask().unwrap());
+        check_golden(&format!("{}_{}_mask", name, refinement_name), &alpha, width, height, 1);
+    }
+
+    // The viewer composites the estimated foreground colors when the pipeline provides them
+    let person = frames.foreground.as_ref().unwrap_or(&frames.high_res_frame);
+    let composite = viewer::blend(&person.as_rgba(), &frames.full_mask().unwrap());
+    let over_black: Vec<u8> = composite
+        .chunks_exact(4)
+        .flat_map(|px| [0, 1, 2].map(|c| (px[c] as u32 * px[3] as u32 / 255) as u8))
+        .collect();
+    check_golden(&format!("{}_composite", name), &over_black, width, height, 3);
+}
+
+fn alpha_at(frames: &RaylibFrames, x: usize, y: usize) -> u8 {
+    let width = frames.high_res_frame.width as usize;
+    255 - frames.full_mask().unwrap()[(y * width + x) * 4 + 3]
+}
+
+#[test]
+fn portrait_matches_golden() {
+    let frames = run_pipeline("portrait.jpg", MaskRefinement::Upsample);
+    assert!(alpha_at(&frames, 960, 300) > 200, "Head should be person");
+    assert!(alpha_at(&frames, 960, 800) > 200, "Torso should be person");
+    assert!(alpha_at(&frames, 100, 100) < 50, "Corner should be background");
+    check_fixture("portrait", &frames);
+}
+
+#[test]
+fn two_people_match_golden() {
+    let frames = run_pipeline("two_people.jpg", MaskRefinement::Upsample);
+    assert!(alpha_at(&frames, 560, 400) > 200, "Left person should be kept");
+    assert!(alpha_at(&frames, 1400, 400) > 200, "Right person should be kept");
+    assert!(alpha_at(&frames, 960, 200) < 50, "Gap between people should be background");
+    check_fixture("two_people", &frames);
+}
+
+#[test]
+fn empty_scene_matches_golden() {
+    let frames = run_pipeline("empty.jpg", MaskRefinement::Upsample);
+    let person = mask_alpha(&frames.ml_low_frame.as_rgba()).iter().filter(|&&a| a >= 128).count();
+    assert_eq!(person, 0, "Empty scene should have no person pixels");
+    check_fixture("empty", &frames);
+}